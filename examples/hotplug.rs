@@ -0,0 +1,25 @@
+use anyhow::Result;
+use std::time::Duration;
+use usb4604::{BridgeEvent, Usb4604, Usb4604Watch};
+
+fn main() -> Result<()> {
+    let usb4604 = Usb4604::open_auto()?;
+    let info = usb4604.info().unwrap().clone();
+    drop(usb4604);
+
+    println!("Power-cycle the hub now");
+    let mut watch = Usb4604Watch::new()?;
+    while let Some(event) = watch.next_event(Some(Duration::from_secs(30))) {
+        println!("{event:?}");
+        if let BridgeEvent::Left(_) = event {
+            break;
+        }
+    }
+
+    let usb4604 = Usb4604::filter()
+        .bus_id(info.bus_id())
+        .port_chain(info.port_chain())
+        .wait_open(Duration::from_secs(30))?;
+    println!("back: {:?}", usb4604.info().map(|i| i.port_chain()));
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use anyhow::Result;
+use usb4604::Usb4604;
+
+fn main() -> Result<()> {
+    for info in Usb4604::list()? {
+        println!(
+            "bus {} port {:?} serial {:?} hub {}",
+            info.bus_id(),
+            info.port_chain(),
+            info.serial_number(),
+            if info.hub().is_some() { "found" } else { "not found" }
+        );
+        if let Ok(topology) = info.topology() {
+            for port in topology.ports() {
+                for device in port.devices() {
+                    println!(
+                        "  port {}: {:04x}:{:04x} {:?}",
+                        port.number(),
+                        device.vendor_id(),
+                        device.product_id(),
+                        device.product_string()
+                    );
+                }
+            }
+        }
+    }
+
+    // Pick a specific hub on a multi-hub setup:
+    // let usb4604 = Usb4604::open_by_serial("0123456789")?;
+    // let usb4604 = Usb4604::open_by_port_path("1", &[2, 5])?;
+    // let usb4604 = Usb4604::filter().bus_id("1").index(0).open()?;
+    // Or the one that a device under test is plugged into:
+    // let usb4604 = Usb4604::filter().upstream_of(&dut_device_info).open()?;
+    Ok(())
+}
@@ -12,7 +12,7 @@ fn main() -> Result<()> {
     for pin in &mut all_pins {
         println!(
             "{}\t{:?}\t{:?}\t{:?}",
-            pin.pio().as_ref(),
+            pin.extended_pio().as_ref(),
             pin.mode(),
             pin.get_output_level()?,
             pin.get_input_level()?
@@ -0,0 +1,21 @@
+use anyhow::Result;
+use usb4604::Usb4604;
+
+fn main() -> Result<()> {
+    let usb4604 = Usb4604::open_auto()?;
+    // only pins without side effects, pass e.g. SideEffect::Unverified to probe more
+    let report = usb4604.probe_pins(&[])?;
+    println!("Pin\tDir\tOutput\tPull-up\tPull-down\tInput follows output");
+    for pin in report {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t\t{}",
+            pin.pio.as_ref(),
+            pin.direction,
+            pin.output,
+            pin.pull_up,
+            pin.pull_down,
+            pin.input_follows_output
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use anyhow::Result;
+use usb4604::sim::{MemoryTarget, SimHub};
+use usb4604::{I2c, Level, Pio, Pull, Usb4604};
+
+fn main() -> Result<()> {
+    // No hardware needed, everything runs against the simulated feature controller.
+    let sim = SimHub::new();
+    let eeprom = MemoryTarget::new(256);
+    sim.add_i2c_target(0x50, eeprom.clone());
+    let usb4604 = Usb4604::new(sim.clone());
+
+    let mut pio0 = usb4604.output(Pio::Pio0, Some(Level::Low))?;
+    pio0.toggle()?;
+    println!("pio0 drives {:?}", sim.output_level(0));
+
+    let input = usb4604.input(Pio::Pio1, Pull::Up)?;
+    sim.drive(1, Some(Level::Low));
+    println!("pio1 is high: {}", input.is_high()?);
+
+    // Mode persists in the IC and is read back when a Flex pin is created.
+    // A pin can only be held by one handle at a time.
+    drop(pio0);
+    let pio0 = usb4604.gpio(Pio::Pio0)?;
+    println!("pio0 mode: {:?}", pio0.mode());
+
+    let mut i2c = usb4604.i2c_bridge()?;
+    i2c.write(0x50, &[0x10, 1, 2, 3])?;
+    let mut data = [0u8; 3];
+    i2c.write_read(0x50, &[0x10], &mut data)?;
+    println!("eeprom: {data:02x?}");
+    println!("missing target: {:?}", i2c.write(0x51, &[0]));
+    Ok(())
+}
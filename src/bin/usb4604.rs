@@ -0,0 +1,530 @@
+//! Poke USB4604 hubs from the command line: GPIOs, raw registers and the I2C bridge.
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::{Value, json};
+use std::error::Error;
+use std::process::ExitCode;
+use strum::IntoEnumIterator;
+use usb4604::pin_map::PinDescriptor;
+use usb4604::sim::{MemoryTarget, SimHub};
+use usb4604::{DeviceFilter, ExtendedPio, Flex, I2c, Level, Pull, ScanOptions, ScanProbe, Usb4604};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+#[derive(Parser)]
+#[command(
+    name = "usb4604",
+    version,
+    about = "Control GPIOs, registers and I2C master of USB4604 hubs"
+)]
+struct Cli {
+    #[command(flatten)]
+    device: DeviceArgs,
+    /// Print JSON instead of human-readable output.
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct DeviceArgs {
+    /// Select the device by serial number.
+    #[arg(long, global = true)]
+    serial: Option<String>,
+    /// Select the device by bus and port chain as printed by `list`, e.g. 1-2.5.
+    #[arg(long, global = true, value_parser = parse_port)]
+    port: Option<(String, Vec<u8>)>,
+    /// Use a simulated hub instead of real hardware, with a 256-byte memory at I2C address 0x50.
+    #[arg(long, global = true)]
+    sim: bool,
+    /// Allow using pins shared with I2C, over-current sense or port power, these functions stop working.
+    #[arg(long, global = true)]
+    allow_side_effects: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List connected devices.
+    List,
+    /// Read or configure a GPIO, pins are given as PIO3, GPIO3 or 3.
+    #[command(subcommand)]
+    Gpio(GpioCommand),
+    /// Read or write a register by address.
+    #[command(subcommand)]
+    Reg(RegCommand),
+    /// Talk to I2C targets through the I2C master of the hub.
+    #[command(subcommand)]
+    I2c(I2cCommand),
+    /// Print all GPIO registers and the state of every pin.
+    Dump,
+}
+
+#[derive(Subcommand)]
+enum GpioCommand {
+    /// Print mode, pull, output and input level.
+    Get {
+        #[arg(value_parser = parse_pin)]
+        pin: ExtendedPio,
+    },
+    /// Make the pin a push-pull output and set its level.
+    Set {
+        #[arg(value_parser = parse_pin)]
+        pin: ExtendedPio,
+        level: LevelArg,
+    },
+    /// Change pin direction, pull configuration is kept.
+    Mode {
+        #[arg(value_parser = parse_pin)]
+        pin: ExtendedPio,
+        mode: ModeArg,
+    },
+    /// Enable or disable pull-up or pull-down resistor.
+    Pull {
+        #[arg(value_parser = parse_pin)]
+        pin: ExtendedPio,
+        pull: PullArg,
+    },
+}
+
+#[derive(Subcommand)]
+enum RegCommand {
+    /// Read a register.
+    Read {
+        #[arg(value_parser = parse_number::<u16>)]
+        addr: u16,
+    },
+    /// Write a register and read it back.
+    Write {
+        #[arg(value_parser = parse_number::<u16>)]
+        addr: u16,
+        #[arg(value_parser = parse_number::<u8>)]
+        value: u8,
+    },
+}
+
+#[derive(Subcommand)]
+enum I2cCommand {
+    /// Read bytes from a target.
+    Read {
+        #[arg(value_parser = parse_number::<u8>)]
+        addr: u8,
+        #[arg(value_parser = parse_number::<usize>)]
+        len: usize,
+    },
+    /// Write bytes to a target.
+    Write {
+        #[arg(value_parser = parse_number::<u8>)]
+        addr: u8,
+        #[arg(value_parser = parse_number::<u8>, required = true)]
+        data: Vec<u8>,
+    },
+    /// Probe all addresses and print the ones that respond, i2cdetect style.
+    Scan {
+        #[arg(long, default_value = "auto")]
+        probe: ProbeArg,
+        /// Also probe addresses 0x00-0x07 and 0x78-0x7F.
+        #[arg(long)]
+        all: bool,
+        /// Skip SMBus special purpose addresses 0x08, 0x0c, 0x28, 0x37 and 0x61.
+        #[arg(long)]
+        skip_reserved: bool,
+    },
+    /// Write bytes, then read with a repeated start, e.g. to read a register of the target.
+    Transfer {
+        #[arg(value_parser = parse_number::<u8>)]
+        addr: u8,
+        #[arg(value_parser = parse_number::<u8>, required = true)]
+        data: Vec<u8>,
+        /// Number of bytes to read.
+        #[arg(long, value_parser = parse_number::<usize>)]
+        read: usize,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LevelArg {
+    Low,
+    High,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ModeArg {
+    Input,
+    Output,
+    OpenDrain,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ProbeArg {
+    Auto,
+    Write,
+    Read,
+    Quick,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PullArg {
+    None,
+    Up,
+    Down,
+}
+
+/// Raw configuration and input bits of a pin.
+struct PinReport {
+    pio: ExtendedPio,
+    is_out: bool,
+    output: bool,
+    input: bool,
+    pull_up: bool,
+    pull_down: bool,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            if cli.json {
+                println!("{}", json!({ "error": e.to_string() }));
+            } else {
+                eprintln!("error: {e}");
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    let (human, json) = match &cli.command {
+        Command::List => list()?,
+        Command::Gpio(command) => gpio(&cli.device, command)?,
+        Command::Reg(command) => reg(&open(&cli.device)?, command)?,
+        Command::I2c(command) => i2c(&open(&cli.device)?, command)?,
+        Command::Dump => dump(&open(&cli.device)?)?,
+    };
+    if cli.json {
+        println!("{json}");
+    } else if !human.is_empty() {
+        println!("{human}");
+    }
+    Ok(())
+}
+
+fn open(device: &DeviceArgs) -> Result<Usb4604> {
+    if device.sim {
+        let sim = SimHub::new();
+        sim.add_i2c_target(0x50, MemoryTarget::new(256));
+        return Ok(Usb4604::new(sim));
+    }
+    let mut filter = DeviceFilter::new();
+    if let Some(serial) = &device.serial {
+        filter = filter.serial_number(serial);
+    }
+    if let Some((bus_id, port_chain)) = &device.port {
+        filter = filter.bus_id(bus_id).port_chain(port_chain);
+    }
+    Ok(filter.open()?)
+}
+
+fn list() -> Result<(String, Value)> {
+    let mut human = Vec::new();
+    let mut devices = Vec::new();
+    for info in Usb4604::list()? {
+        let port = format_port(info.bus_id(), info.port_chain());
+        let chip = info.chip_variant().map(|v| v.as_ref().to_string());
+        human.push(format!(
+            "{port}\tserial {}\t{}",
+            info.serial_number().unwrap_or("-"),
+            chip.as_deref().unwrap_or("unknown hub")
+        ));
+        devices.push(json!({
+            "port": port,
+            "bus_id": info.bus_id(),
+            "port_chain": info.port_chain(),
+            "serial_number": info.serial_number(),
+            "chip_variant": chip,
+        }));
+    }
+    if human.is_empty() {
+        human.push("no devices found".to_string());
+    }
+    Ok((human.join("\n"), Value::Array(devices)))
+}
+
+fn gpio(device: &DeviceArgs, command: &GpioCommand) -> Result<(String, Value)> {
+    let usb4604 = open(device)?;
+    let pio = match command {
+        GpioCommand::Get { pin }
+        | GpioCommand::Set { pin, .. }
+        | GpioCommand::Mode { pin, .. }
+        | GpioCommand::Pull { pin, .. } => *pin,
+    };
+    let pin = usb4604
+        .chip_variant()
+        .pin(pio)
+        .ok_or(usb4604::Error::PinNotAvailable(pio))?;
+    if !matches!(command, GpioCommand::Get { .. }) {
+        let mut flex = open_pin(&usb4604, device, pio)?;
+        match *command {
+            GpioCommand::Get { .. } => {}
+            GpioCommand::Set { level, .. } => flex.set_as_output(Some(level.into()))?,
+            GpioCommand::Mode { mode, .. } => {
+                let pull = read_pin(&usb4604, pin)?.pull();
+                match mode {
+                    ModeArg::Input => flex.set_as_input(pull)?,
+                    ModeArg::Output => {
+                        // set_as_output disables pulls, put them back as promised
+                        flex.set_as_output(None)?;
+                        flex.set_pull(pull)?;
+                    }
+                    ModeArg::OpenDrain => flex.set_as_open_drain(pull)?,
+                }
+            }
+            GpioCommand::Pull { pull, .. } => flex.set_pull(pull.into())?,
+        }
+    }
+    let report = read_pin(&usb4604, pin)?;
+    Ok((report.human(), report.json()))
+}
+
+fn open_pin(usb4604: &Usb4604, device: &DeviceArgs, pio: ExtendedPio) -> Result<Flex> {
+    let acknowledged = if device.allow_side_effects {
+        pio.side_effects()
+    } else {
+        &[]
+    };
+    usb4604
+        .gpio_extended(pio, acknowledged)
+        .map_err(|e| match e {
+            usb4604::Error::UnacknowledgedSideEffect(effect) => format!(
+                "{} has side effect {effect:?}, pass --allow-side-effects to use it anyway",
+                pio.as_ref()
+            )
+            .into(),
+            e => e.into(),
+        })
+}
+
+fn read_pin(usb4604: &Usb4604, pin: &PinDescriptor) -> Result<PinReport> {
+    let regs = pin.registers;
+    let bit = |addr| -> Result<bool> { Ok(usb4604.read_addr(addr)? & (1 << pin.bit) != 0) };
+    Ok(PinReport {
+        pio: pin.pio,
+        is_out: bit(regs.dir)?,
+        output: bit(regs.output)?,
+        input: bit(regs.input)?,
+        pull_up: bit(regs.pull_up)?,
+        pull_down: bit(regs.pull_down)?,
+    })
+}
+
+fn reg(usb4604: &Usb4604, command: &RegCommand) -> Result<(String, Value)> {
+    let addr = match *command {
+        RegCommand::Read { addr } => addr,
+        RegCommand::Write { addr, value } => {
+            usb4604.write_addr(addr, value)?;
+            addr
+        }
+    };
+    let value = usb4604.read_addr(addr)?;
+    Ok((
+        format!("{addr:#06x}: {value:#04x}"),
+        json!({ "address": addr, "value": value }),
+    ))
+}
+
+fn i2c(usb4604: &Usb4604, command: &I2cCommand) -> Result<(String, Value)> {
+    let mut i2c = usb4604.i2c_bridge()?;
+    if let I2cCommand::Scan {
+        probe,
+        all,
+        skip_reserved,
+    } = *command
+    {
+        let range = if all { 0x00..=0x7F } else { 0x08..=0x77 };
+        let options = ScanOptions::new()
+            .probe(probe.into())
+            .range(range)
+            .skip_reserved(skip_reserved);
+        let result = i2c.scan_with(&options)?;
+        let human = result.to_string().trim_end().to_string();
+        return Ok((human, json!({ "addresses": result.addresses() })));
+    }
+    let (addr, read) = match command {
+        I2cCommand::Scan { .. } => unreachable!("handled above"),
+        I2cCommand::Read { addr, len } => {
+            let mut buf = vec![0; *len];
+            i2c.read(*addr, &mut buf)?;
+            (*addr, buf)
+        }
+        I2cCommand::Write { addr, data } => {
+            i2c.write(*addr, data)?;
+            (*addr, Vec::new())
+        }
+        I2cCommand::Transfer { addr, data, read } => {
+            let mut buf = vec![0; *read];
+            i2c.write_read(*addr, data, &mut buf)?;
+            (*addr, buf)
+        }
+    };
+    let human = read
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    Ok((human, json!({ "address": addr, "data": read })))
+}
+
+fn dump(usb4604: &Usb4604) -> Result<(String, Value)> {
+    let variant = usb4604.chip_variant();
+    let mut human = vec!["Bank\tDir\tOutput\tInput\tPullUp\tPullDown".to_string()];
+    let mut registers = Vec::new();
+    for bank in variant.banks() {
+        let named = [
+            ("dir", bank.dir),
+            ("output", bank.output),
+            ("input", bank.input),
+            ("pull_up", bank.pull_up),
+            ("pull_down", bank.pull_down),
+        ];
+        let mut line = format!("{:#06x}", bank.dir);
+        for (name, addr) in named {
+            let value = usb4604.read_addr(addr)?;
+            line.push_str(&format!("\t{value:#04x}"));
+            registers.push(json!({ "register": name, "address": addr, "value": value }));
+        }
+        human.push(line);
+    }
+    human.push(String::new());
+    human.push("Pin\tMode\tPull\tOutput\tInput".to_string());
+    let mut reports = Vec::new();
+    for pin in variant.pins() {
+        let report = read_pin(usb4604, pin)?;
+        human.push(report.human());
+        reports.push(report.json());
+    }
+    Ok((
+        human.join("\n"),
+        json!({ "chip_variant": usb4604.chip_variant().as_ref(), "registers": registers, "pins": reports }),
+    ))
+}
+
+impl PinReport {
+    fn mode(&self) -> &'static str {
+        if self.is_out { "output" } else { "input" }
+    }
+
+    /// Pull that the pin is put back to when its mode changes, both resistors enabled is reported as up.
+    fn pull(&self) -> Pull {
+        match (self.pull_up, self.pull_down) {
+            (true, _) => Pull::Up,
+            (false, true) => Pull::Down,
+            (false, false) => Pull::None,
+        }
+    }
+
+    fn pull_name(&self) -> &'static str {
+        match (self.pull_up, self.pull_down) {
+            (true, true) => "up+down",
+            (true, false) => "up",
+            (false, true) => "down",
+            (false, false) => "none",
+        }
+    }
+
+    fn human(&self) -> String {
+        let level = |high| if high { "high" } else { "low" };
+        format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.pio.as_ref(),
+            self.mode(),
+            self.pull_name(),
+            level(self.output),
+            level(self.input)
+        )
+    }
+
+    fn json(&self) -> Value {
+        json!({
+            "pin": self.pio.as_ref(),
+            "number": self.pio.number(),
+            "mode": self.mode(),
+            "pull": self.pull_name(),
+            "output": self.output,
+            "input": self.input,
+        })
+    }
+}
+
+impl From<LevelArg> for Level {
+    fn from(level: LevelArg) -> Level {
+        match level {
+            LevelArg::Low => Level::Low,
+            LevelArg::High => Level::High,
+        }
+    }
+}
+
+impl From<ProbeArg> for ScanProbe {
+    fn from(probe: ProbeArg) -> ScanProbe {
+        match probe {
+            ProbeArg::Auto => ScanProbe::Auto,
+            ProbeArg::Write => ScanProbe::ZeroLengthWrite,
+            ProbeArg::Read => ScanProbe::ReadByte,
+            ProbeArg::Quick => ScanProbe::SmbusQuick,
+        }
+    }
+}
+
+impl From<PullArg> for Pull {
+    fn from(pull: PullArg) -> Pull {
+        match pull {
+            PullArg::None => Pull::None,
+            PullArg::Up => Pull::Up,
+            PullArg::Down => Pull::Down,
+        }
+    }
+}
+
+fn parse_pin(s: &str) -> std::result::Result<ExtendedPio, String> {
+    let lower = s.to_ascii_lowercase();
+    let number = lower
+        .strip_prefix("gpio")
+        .or_else(|| lower.strip_prefix("pio"))
+        .unwrap_or(&lower);
+    let number: u8 = number.parse().map_err(|_| format!("not a pin: {s}"))?;
+    ExtendedPio::iter()
+        .find(|pio| pio.number() == number)
+        .ok_or_else(|| format!("GPIO{number} is not in the register map"))
+}
+
+/// Parse decimal or 0x-prefixed hex number.
+fn parse_number<T: TryFrom<u64>>(s: &str) -> std::result::Result<T, String> {
+    let value = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("not a number: {s}"))?;
+    T::try_from(value).map_err(|_| format!("out of range: {s}"))
+}
+
+/// Parse `bus-port.port...`, the format used by `list`.
+fn parse_port(s: &str) -> std::result::Result<(String, Vec<u8>), String> {
+    let (bus_id, chain) = s
+        .rsplit_once('-')
+        .ok_or_else(|| format!("expected bus-port.port, e.g. 1-2.5: {s}"))?;
+    let chain = chain
+        .split('.')
+        .map(|port| {
+            port.parse()
+                .map_err(|_| format!("not a port number: {port}"))
+        })
+        .collect::<std::result::Result<_, _>>()?;
+    Ok((bus_id.to_string(), chain))
+}
+
+fn format_port(bus_id: &str, port_chain: &[u8]) -> String {
+    let chain: Vec<String> = port_chain.iter().map(u8::to_string).collect();
+    format!("{bus_id}-{}", chain.join("."))
+}
@@ -0,0 +1,158 @@
+use crate::blocking::block_on;
+use crate::{DeviceFilter, Error, I2cBridge, Transport, Usb4604};
+use std::time::Duration;
+
+/// Timeout and retry settings of one kind of transfer.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TransferPolicy {
+    pub(crate) timeout: Duration,
+    pub(crate) retries: u32,
+    pub(crate) retry_on_nack: bool,
+    pub(crate) backoff: Duration,
+}
+
+impl TransferPolicy {
+    const fn new(timeout: Duration) -> TransferPolicy {
+        TransferPolicy {
+            timeout,
+            retries: 0,
+            retry_on_nack: false,
+            backoff: Duration::from_millis(10),
+        }
+    }
+
+    /// Delay before the given retry, starting from 0, doubled every time.
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        self.backoff.saturating_mul(1 << retry.min(16))
+    }
+}
+
+/// Default for register access, see [Usb4604Builder].
+pub(crate) const REGISTER_POLICY: TransferPolicy = TransferPolicy::new(Duration::from_millis(500));
+/// Default for I2C transfers, see [I2cBridgeBuilder].
+pub(crate) const I2C_POLICY: TransferPolicy = TransferPolicy::new(Duration::from_millis(100));
+
+/// Timeout and retries of register reads and writes, see [Usb4604::builder].
+///
+/// A failed register transfer is repeated up to [retries](Self::retries) times, unless the device was disconnected.
+/// Each read or write of a read-modify-write is retried on its own, while the register stays locked.
+#[derive(Clone, Debug)]
+pub struct Usb4604Builder {
+    policy: TransferPolicy,
+}
+
+/// Timeout and retries of I2C transactions, see [Usb4604::i2c_bridge_builder].
+///
+/// A transaction is only repeated if its very first USB transfer failed, so a transaction that was partially
+/// carried out on the bus, e.g. a register address write followed by a failed read, is never sent twice.
+/// NACK is only retried if [enabled](Self::retry_on_nack), a short read never is.
+/// Neither is a timeout of a first transfer that writes data: the USB status stage can time out after the write
+/// completed on the bus, e.g. an EEPROM page write, so sending it again could write twice.
+pub struct I2cBridgeBuilder {
+    usb4604: Usb4604,
+    policy: TransferPolicy,
+    max_chunk_size: usize,
+}
+
+impl Usb4604Builder {
+    /// 500 ms timeout and no retries.
+    pub fn new() -> Usb4604Builder {
+        Usb4604Builder {
+            policy: REGISTER_POLICY,
+        }
+    }
+
+    /// Time allowed for each register transfer.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.policy.timeout = timeout;
+        self
+    }
+
+    /// How many times a failed register transfer is repeated, 0 by default.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.policy.retries = retries;
+        self
+    }
+
+    /// Delay before the first retry, doubled for every next one, 10 ms by default.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.policy.backoff = backoff;
+        self
+    }
+
+    /// Create Usb4604 from any [Transport], see [Usb4604::new].
+    pub fn build(&self, transport: impl Transport + 'static) -> Usb4604 {
+        let usb4604 = Usb4604::new(transport);
+        usb4604.set_register_policy(self.policy);
+        usb4604
+    }
+
+    /// Find and open the device selected by filter, see [DeviceFilter::open].
+    pub fn open(&self, filter: &DeviceFilter) -> Result<Usb4604, Error> {
+        let usb4604 = filter.open()?;
+        usb4604.set_register_policy(self.policy);
+        Ok(usb4604)
+    }
+}
+
+impl Default for Usb4604Builder {
+    fn default() -> Self {
+        Usb4604Builder::new()
+    }
+}
+
+impl I2cBridgeBuilder {
+    pub(crate) fn new(usb4604: Usb4604) -> I2cBridgeBuilder {
+        I2cBridgeBuilder {
+            usb4604,
+            policy: I2C_POLICY,
+            max_chunk_size: crate::i2c::DEFAULT_MAX_CHUNK_SIZE,
+        }
+    }
+
+    /// Time allowed for each USB transfer of a transaction and for entering pass-through mode, 100 ms by default.
+    ///
+    /// Raise it for targets that stretch the clock for long, e.g. EEPROMs busy with a page write.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.policy.timeout = timeout;
+        self
+    }
+
+    /// How many times a failed transaction is repeated, 0 by default.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.policy.retries = retries;
+        self
+    }
+
+    /// Also retry when the target does not acknowledge, disabled by default.
+    ///
+    /// Useful for targets that are briefly unresponsive, e.g. EEPROMs during a write cycle.
+    pub fn retry_on_nack(mut self, retry: bool) -> Self {
+        self.policy.retry_on_nack = retry;
+        self
+    }
+
+    /// Delay before the first retry, doubled for every next one, 10 ms by default.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.policy.backoff = backoff;
+        self
+    }
+
+    /// See [I2cBridge::set_max_chunk_size].
+    pub fn max_chunk_size(mut self, size: usize) -> Self {
+        self.max_chunk_size = size;
+        self
+    }
+
+    /// Enable I2C bridging and return [I2cBridge].
+    pub fn build(self) -> Result<I2cBridge, Error> {
+        block_on(self.build_async())
+    }
+
+    /// Async version of [build](Self::build).
+    pub async fn build_async(self) -> Result<I2cBridge, Error> {
+        let mut i2c = I2cBridge::init(self.usb4604, self.policy).await?;
+        i2c.set_max_chunk_size(self.max_chunk_size);
+        Ok(i2c)
+    }
+}
@@ -0,0 +1,94 @@
+use crate::pin_map::{BANKS, PinDescriptor, PinRegisters, USB4604_PINS};
+use crate::{ExtendedPio, SideEffect};
+use strum::{AsRefStr, EnumIter, IntoEnumIterator};
+
+/// Hub ICs of the same family, sharing the feature controller and register map of USB4604.
+///
+/// Detected from the product id of the hub device, see [Usb4604::chip_variant](crate::Usb4604::chip_variant).
+/// Pins that double as over-current sense input or port power output of a port that the chip does not have
+/// are considered to be missing.
+#[derive(Clone, Copy, PartialEq, Eq, EnumIter, AsRefStr, Debug)]
+pub enum ChipVariant {
+    Usb2532,
+    Usb2533,
+    Usb2534,
+    Usb3613,
+    Usb3813,
+    Usb4604,
+    Usb4624,
+}
+
+impl ChipVariant {
+    /// Default product id of the hub device.
+    ///
+    /// Only the USB4604 one was seen on real hardware, the others are the reset defaults of the Product ID
+    /// (PIDL/PIDM) configuration registers in the Microchip datasheet of each chip, not verified on hardware.
+    /// Hubs configured with a custom PID are not detected, set the variant manually in that case.
+    pub fn hub_product_id(self) -> u16 {
+        match self {
+            ChipVariant::Usb2532 => 0x2532,
+            ChipVariant::Usb2533 => 0x2533,
+            ChipVariant::Usb2534 => 0x2534,
+            ChipVariant::Usb3613 => 0x3613,
+            ChipVariant::Usb3813 => 0x3813,
+            ChipVariant::Usb4604 => 0x4502,
+            ChipVariant::Usb4624 => 0x4504,
+        }
+    }
+
+    /// Find the variant by the product id of its hub device.
+    pub fn from_hub_product_id(product_id: u16) -> Option<ChipVariant> {
+        ChipVariant::iter().find(|v| v.hub_product_id() == product_id)
+    }
+
+    /// Number of physical downstream ports, the internal port of the feature controller is not counted.
+    pub fn downstream_ports(self) -> u8 {
+        match self {
+            ChipVariant::Usb2532 => 2,
+            ChipVariant::Usb2533 | ChipVariant::Usb3613 | ChipVariant::Usb3813 => 3,
+            ChipVariant::Usb2534 | ChipVariant::Usb4604 | ChipVariant::Usb4624 => 4,
+        }
+    }
+
+    /// All GPIOs that exist on this chip, in [USB4604_PINS] order.
+    ///
+    /// Derived from the port numbers in [side effects](ExtendedPio::side_effects): a pin that is
+    /// the over-current sense input or power enable output of a port the chip does not have is missing.
+    pub fn pins(self) -> impl Iterator<Item = &'static PinDescriptor> {
+        USB4604_PINS.iter().filter(move |pin| self.has_pin(pin))
+    }
+
+    /// Returns pin description, or None if the pin does not exist on this chip.
+    pub fn pin(self, pio: ExtendedPio) -> Option<&'static PinDescriptor> {
+        self.pins().find(|pin| pin.pio == pio)
+    }
+
+    /// GPIO register banks that control at least one pin of this chip.
+    pub fn banks(self) -> impl Iterator<Item = &'static PinRegisters> {
+        BANKS
+            .iter()
+            .filter(move |bank| self.pins().any(|pin| pin.registers == **bank))
+    }
+
+    /// Returns true if the register belongs to one of the [banks](Self::banks) of this chip.
+    pub fn has_register(self, addr: u16) -> bool {
+        self.banks().any(|bank| {
+            [
+                bank.dir,
+                bank.output,
+                bank.input,
+                bank.pull_up,
+                bank.pull_down,
+            ]
+            .contains(&addr)
+        })
+    }
+
+    fn has_pin(self, pin: &PinDescriptor) -> bool {
+        pin.side_effects.iter().all(|effect| match *effect {
+            SideEffect::DisablesOverCurrentSense { port }
+            | SideEffect::DisablesPortPower { port } => port <= self.downstream_ports(),
+            SideEffect::DisablesI2c | SideEffect::Unverified => true,
+        })
+    }
+}
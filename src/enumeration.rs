@@ -0,0 +1,266 @@
+use crate::usb4604_hal::{PRODUCT_BRIDGE_DEV, VENDOR_SMSC};
+use crate::{ChipVariant, Error, Usb4604};
+use nusb::{DeviceInfo, MaybeFuture};
+use std::sync::Arc;
+
+/// USB4604 feature controller found during enumeration, see [Usb4604::list].
+#[derive(Clone, Debug)]
+pub struct Usb4604Info {
+    device: DeviceInfo,
+    hub: Option<DeviceInfo>,
+}
+
+impl Usb4604Info {
+    /// Serial number string of the feature controller, if it has one.
+    pub fn serial_number(&self) -> Option<&str> {
+        self.device.serial_number()
+    }
+
+    /// Identifier of the bus the device is connected to, this is the bus number on Linux.
+    pub fn bus_id(&self) -> &str {
+        self.device.bus_id()
+    }
+
+    /// Path of port numbers from the root hub to the feature controller.
+    /// Stays the same across hub resets and reboots, as long as the hub is plugged into the same physical port.
+    pub fn port_chain(&self) -> &[u8] {
+        self.device.port_chain()
+    }
+
+    /// nusb enumeration data of the feature controller.
+    pub fn device_info(&self) -> &DeviceInfo {
+        &self.device
+    }
+
+    /// Hub device of the same IC, the feature controller is attached to one of its ports.
+    /// None if the hub was not found, e.g. when it is configured with a custom PID.
+    pub fn hub(&self) -> Option<&DeviceInfo> {
+        self.hub.as_ref()
+    }
+
+    /// Chip variant detected from the hub product id, None if the hub was not found.
+    pub fn chip_variant(&self) -> Option<ChipVariant> {
+        self.hub
+            .as_ref()
+            .and_then(|hub| ChipVariant::from_hub_product_id(hub.product_id()))
+    }
+
+    /// Enumerate devices connected to the downstream ports of the hub this feature controller belongs to.
+    pub fn topology(&self) -> Result<HubTopology, Error> {
+        let devices: Vec<_> = nusb::list_devices().wait()?.collect();
+        let hub = devices
+            .iter()
+            .find(|h| is_parent_hub(h, &self.device))
+            .ok_or(Error::Other("USB4604 hub device not found"))?;
+        let own_port = self.device.port_chain().last().copied();
+        let variant = ChipVariant::from_hub_product_id(hub.product_id())
+            .expect("only known hub variants are matched");
+        let ports = (1..=variant.downstream_ports())
+            .filter(|&number| Some(number) != own_port)
+            .map(|number| {
+                let mut devices: Vec<_> = devices
+                    .iter()
+                    .filter(|d| is_behind_port(d, hub, number))
+                    .cloned()
+                    .collect();
+                devices.sort_by(|a, b| a.port_chain().cmp(b.port_chain()));
+                DownstreamPort { number, devices }
+            })
+            .collect();
+        Ok(HubTopology {
+            hub: hub.clone(),
+            ports,
+        })
+    }
+
+    /// Returns true if the device is connected, directly or through other hubs, to this feature controller's hub.
+    pub fn is_upstream_of(&self, device: &DeviceInfo) -> bool {
+        let Some((_, hub_chain)) = self.port_chain().split_last() else {
+            return false;
+        };
+        device.bus_id() == self.bus_id()
+            && device.port_chain().len() > hub_chain.len()
+            && device.port_chain().starts_with(hub_chain)
+            && device.port_chain() != self.port_chain()
+    }
+
+    /// Open the device and claim the feature controller interface.
+    pub fn open(&self) -> Result<Usb4604, Error> {
+        let device = self.device.open().wait()?;
+        let interface = device.claim_interface(0).wait()?;
+        Ok(Usb4604::from_parts(Arc::new(interface), Some(self.clone())))
+    }
+
+    pub(crate) fn from_devices(devices: &[DeviceInfo]) -> Vec<Usb4604Info> {
+        let mut found: Vec<Usb4604Info> = devices
+            .iter()
+            .filter(|d| d.vendor_id() == VENDOR_SMSC && d.product_id() == PRODUCT_BRIDGE_DEV)
+            .map(|d| Usb4604Info {
+                device: d.clone(),
+                hub: devices.iter().find(|h| is_parent_hub(h, d)).cloned(),
+            })
+            .collect();
+        found.sort_by(|a, b| (a.bus_id(), a.port_chain()).cmp(&(b.bus_id(), b.port_chain())));
+        found
+    }
+}
+
+fn is_behind_port(device: &DeviceInfo, hub: &DeviceInfo, port: u8) -> bool {
+    device.bus_id() == hub.bus_id()
+        && device
+            .port_chain()
+            .strip_prefix(hub.port_chain())
+            .is_some_and(|rest| rest.first() == Some(&port))
+}
+
+fn is_parent_hub(hub: &DeviceInfo, device: &DeviceInfo) -> bool {
+    let Some((_, parent_chain)) = device.port_chain().split_last() else {
+        return false;
+    };
+    hub.vendor_id() == VENDOR_SMSC
+        && ChipVariant::from_hub_product_id(hub.product_id()).is_some()
+        && hub.bus_id() == device.bus_id()
+        && hub.port_chain() == parent_chain
+}
+
+/// Select one of several connected devices, created with [Usb4604::filter].
+///
+/// All criteria that were set must match. Matching devices are ordered by bus and port chain, so
+/// [index](Self::index) is stable as long as the physical setup does not change.
+#[derive(Clone, Default, Debug)]
+pub struct DeviceFilter {
+    serial_number: Option<String>,
+    bus_id: Option<String>,
+    port_chain: Option<Vec<u8>>,
+    downstream: Option<DeviceInfo>,
+    index: Option<usize>,
+}
+
+impl DeviceFilter {
+    /// Filter that matches any USB4604 feature controller.
+    pub fn new() -> DeviceFilter {
+        DeviceFilter::default()
+    }
+
+    /// Only match a device with this serial number.
+    pub fn serial_number(mut self, serial_number: impl Into<String>) -> Self {
+        self.serial_number = Some(serial_number.into());
+        self
+    }
+
+    /// Only match devices on this bus.
+    pub fn bus_id(mut self, bus_id: impl Into<String>) -> Self {
+        self.bus_id = Some(bus_id.into());
+        self
+    }
+
+    /// Only match a device connected through this port chain, see [Usb4604Info::port_chain].
+    pub fn port_chain(mut self, port_chain: &[u8]) -> Self {
+        self.port_chain = Some(port_chain.to_vec());
+        self
+    }
+
+    /// Only match the device whose hub the given device is plugged into, directly or through other hubs.
+    pub fn upstream_of(mut self, device: &DeviceInfo) -> Self {
+        self.downstream = Some(device.clone());
+        self
+    }
+
+    /// Pick n-th of the matching devices instead of requiring exactly one.
+    pub fn index(mut self, index: usize) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Returns true if a device satisfies all criteria except index.
+    pub fn matches(&self, info: &Usb4604Info) -> bool {
+        self.serial_number
+            .as_deref()
+            .is_none_or(|s| info.serial_number() == Some(s))
+            && self.bus_id.as_deref().is_none_or(|b| info.bus_id() == b)
+            && self
+                .port_chain
+                .as_deref()
+                .is_none_or(|p| info.port_chain() == p)
+            && self
+                .downstream
+                .as_ref()
+                .is_none_or(|d| info.is_upstream_of(d))
+    }
+
+    /// Enumerate and return all devices satisfying all criteria except index.
+    pub fn list(&self) -> Result<Vec<Usb4604Info>, Error> {
+        Ok(Usb4604::list()?
+            .into_iter()
+            .filter(|info| self.matches(info))
+            .collect())
+    }
+
+    /// Enumerate and return the only matching device, or the one selected with [index](Self::index).
+    pub fn find(&self) -> Result<Usb4604Info, Error> {
+        let mut found = self.list()?;
+        match self.index {
+            Some(index) if index < found.len() => Ok(found.swap_remove(index)),
+            Some(_) => Err(Error::NoDevicesFound),
+            None if found.len() > 1 => Err(Error::MultipleDevicesFound),
+            None => found.pop().ok_or(Error::NoDevicesFound),
+        }
+    }
+
+    /// Find and open the selected device.
+    pub fn open(&self) -> Result<Usb4604, Error> {
+        self.find()?.open()
+    }
+}
+
+/// Hub of a USB4604 IC and devices plugged into it, see [Usb4604::topology].
+#[derive(Clone, Debug)]
+pub struct HubTopology {
+    hub: DeviceInfo,
+    ports: Vec<DownstreamPort>,
+}
+
+/// Downstream port of the hub and devices connected to it.
+#[derive(Clone, Debug)]
+pub struct DownstreamPort {
+    number: u8,
+    devices: Vec<DeviceInfo>,
+}
+
+impl HubTopology {
+    /// nusb enumeration data of the hub device.
+    pub fn hub(&self) -> &DeviceInfo {
+        &self.hub
+    }
+
+    /// Downstream ports of the hub, except the internal one used by the feature controller.
+    pub fn ports(&self) -> &[DownstreamPort] {
+        &self.ports
+    }
+
+    /// Returns hub port number the device is connected to, directly or through other hubs.
+    pub fn port_of(&self, device: &DeviceInfo) -> Option<u8> {
+        self.ports
+            .iter()
+            .find(|p| is_behind_port(device, &self.hub, p.number))
+            .map(|p| p.number)
+    }
+}
+
+impl DownstreamPort {
+    /// Port number, starting from 1.
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    /// Devices connected to this port, the one plugged directly into the port comes first,
+    /// followed by devices connected through it if it is a hub.
+    pub fn devices(&self) -> &[DeviceInfo] {
+        &self.devices
+    }
+
+    /// Returns true if nothing is connected to this port.
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+}
@@ -1,6 +1,11 @@
-use crate::usb4604_reg::*;
+use crate::blocking::block_on;
+use crate::pin_map::PinDescriptor;
+use crate::wait::{Condition, wait_for};
 use crate::{Error, Usb4604};
-use strum::{AsRefStr, EnumIter};
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin, StatefulOutputPin};
+use embedded_hal_async::digital::Wait;
+use std::thread;
+use strum::{AsRefStr, EnumIter, IntoEnumIterator};
 
 /// GPIO configured as Push-Pull output.
 pub struct PushPullOutput {
@@ -23,9 +28,71 @@ pub struct OpenDrainOutput {
 /// Modeled after embassy Flex.
 pub struct Flex {
     usb4604: Usb4604,
-    pio: Pio,
+    pin: &'static PinDescriptor,
     mode: Mode,
     is_out_en: bool,
+    /// Key of this pin's configuration tracked in [Usb4604] for replay after reconnect.
+    id: u64,
+    drop_policy: DropPolicy,
+    /// Configuration of the pin when [DropPolicy::Restore] was first chosen, only read then.
+    restore: Option<Snapshot>,
+}
+
+/// What happens to the pin when its handle is dropped, see [Flex::set_drop_policy].
+///
+/// Applied on a best-effort basis: errors are ignored, and a dropped handle blocks until the transfers are done,
+/// also when dropped in async code. Unwinding after a panic drops handles too, so a policy other than
+/// [Keep](Self::Keep) leaves the hardware in a known state after a crashed test.
+///
+/// If a read-modify-write of another handle is in progress at that moment, e.g. in a task paused on the same
+/// single-threaded executor, waiting for it could deadlock. The policy is then applied on a helper thread instead,
+/// and the pin is claimable again once it is done.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum DropPolicy {
+    /// Pin is left as it is, default.
+    #[default]
+    Keep,
+    /// Pin is reverted to input with no pull-up or pull-down.
+    Input,
+    /// Direction, output level and pulls the pin had when this policy was first chosen are written back.
+    ///
+    /// To get back the configuration from before the pin was claimed, open it with [Usb4604::gpio] and choose
+    /// the policy before changing the mode.
+    Restore,
+    /// Pin is driven to the given level in push-pull mode.
+    Drive(Level),
+}
+
+/// Raw configuration bits of a pin.
+#[derive(Copy, Clone, Debug)]
+struct Snapshot {
+    is_out: bool,
+    output: bool,
+    pull_up: bool,
+    pull_down: bool,
+}
+
+/// Every verified GPIO of the chip, each handed out exactly once, see [Usb4604::split].
+pub struct Pins {
+    pub pio0: Flex,
+    pub pio1: Flex,
+    pub pio3: Flex,
+    pub pio8: Flex,
+    pub pio9: Flex,
+    pub pio10: Flex,
+    /// None on 2-port chips.
+    pub pio19: Option<Flex>,
+    /// None on 2 and 3-port chips.
+    pub pio20: Option<Flex>,
+}
+
+/// Last configuration applied through a pin handle, None for things that were never set.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct PinState {
+    pin: &'static PinDescriptor,
+    is_out_en: bool,
+    level: Option<Level>,
+    pull: Option<Pull>,
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -49,7 +116,6 @@ pub enum Level {
 }
 
 /// Enum over all known to be working IO's.
-// Can be implemented in a more abstract way, but since there are so few IOs, it does not worth it.
 #[derive(Clone, Copy, EnumIter, AsRefStr, PartialEq, Debug)]
 pub enum Pio {
     Pio0,
@@ -62,55 +128,168 @@ pub enum Pio {
     Pio20,
 }
 
+/// Enum over all IO's that have bits in the register map, including multi-function pins that are not in [Pio].
+///
+/// Using most of them as GPIO changes how the hub behaves, see [side_effects](Self::side_effects),
+/// so they can only be opened with [Usb4604::gpio_extended], which requires acknowledging those.
+#[derive(Clone, Copy, EnumIter, AsRefStr, PartialEq, Eq, Debug)]
+pub enum ExtendedPio {
+    Pio0,
+    Pio1,
+    Pio2,
+    Pio3,
+    Pio5,
+    Pio8,
+    Pio9,
+    Pio10,
+    Pio17,
+    Pio18,
+    Pio19,
+    Pio20,
+    Pio41,
+    Pio42,
+    Pio43,
+    Pio44,
+    Pio45,
+}
+
+/// What else changes in the hub when a multi-function pin is used as GPIO, see [ExtendedPio::side_effects].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SideEffect {
+    /// Pin is shared with the I2C master, [I2cBridge](crate::I2cBridge) stops working.
+    DisablesI2c,
+    /// Pin is the over-current sense input of the port. The port is disabled, or for ports 2-4 the hub may be put
+    /// into ganged over-current sensing mode instead, depending on hub configuration.
+    DisablesOverCurrentSense { port: u8 },
+    /// Pin is the power enable output of the port. The port is disabled, or for ports 2-4 the hub may be put
+    /// into ganged port power mode instead, depending on hub configuration.
+    DisablesPortPower { port: u8 },
+    /// Pin is not in [Pio], it was never verified to be controllable from the USB interface.
+    Unverified,
+}
+
+impl From<Pio> for ExtendedPio {
+    fn from(pio: Pio) -> Self {
+        match pio {
+            Pio::Pio0 => ExtendedPio::Pio0,
+            Pio::Pio1 => ExtendedPio::Pio1,
+            Pio::Pio3 => ExtendedPio::Pio3,
+            Pio::Pio8 => ExtendedPio::Pio8,
+            Pio::Pio9 => ExtendedPio::Pio9,
+            Pio::Pio10 => ExtendedPio::Pio10,
+            Pio::Pio19 => ExtendedPio::Pio19,
+            Pio::Pio20 => ExtendedPio::Pio20,
+        }
+    }
+}
+
+impl ExtendedPio {
+    /// Returns the same pin as [Pio] if it is one of the verified ones.
+    pub(crate) fn verified(self) -> Option<Pio> {
+        Pio::iter().find(|pio| self == *pio)
+    }
+}
+
+impl PartialEq<Pio> for ExtendedPio {
+    fn eq(&self, other: &Pio) -> bool {
+        *self == ExtendedPio::from(*other)
+    }
+}
+
 impl Flex {
-    pub(crate) fn init_get_mode(usb4604: Usb4604, pio: Pio) -> Result<Flex, Error> {
-        let is_out = match pio {
-            Pio::Pio0 => usb4604.read_reg::<Gpio0_7Dir>()?.gpio0_out_en(),
-            Pio::Pio1 => usb4604.read_reg::<Gpio0_7Dir>()?.gpio1_out_en(),
-            Pio::Pio3 => usb4604.read_reg::<Gpio0_7Dir>()?.gpio3_out_en(),
-            Pio::Pio8 => usb4604.read_reg::<Gpio8_10Dir>()?.gpio8_out_en(),
-            Pio::Pio9 => usb4604.read_reg::<Gpio8_10Dir>()?.gpio9_out_en(),
-            Pio::Pio10 => usb4604.read_reg::<Gpio8_10Dir>()?.gpio10_out_en(),
-            Pio::Pio19 => usb4604.read_reg::<Gpio17_20Dir>()?.gpio19_out_en(),
-            Pio::Pio20 => usb4604.read_reg::<Gpio17_20Dir>()?.gpio20_out_en(),
-        };
-        let mode = if is_out {
-            Mode::OutputPushPull
-        } else {
-            Mode::Input
-        };
-        Ok(Flex {
-            usb4604,
-            pio,
-            mode,
-            is_out_en: is_out,
-        })
+    /// Claim the pin and take its mode from the IC.
+    pub(crate) async fn init_get_mode(
+        usb4604: Usb4604,
+        pin: &'static PinDescriptor,
+    ) -> Result<Flex, Error> {
+        let mut flex = Flex::init_ignore_mode(usb4604, pin).await?;
+        if read_bit(&flex.usb4604, pin.registers.dir, pin).await? {
+            flex.record_dir(Mode::OutputPushPull, true);
+        }
+        Ok(flex)
     }
 
-    /// Create Flex pin assuming input mode, intended use is to immediately call one of the into_ methods.
-    pub(crate) fn init_ignore_mode(usb4604: Usb4604, pio: Pio) -> Flex {
-        Flex {
+    /// Claim the pin, assuming it is an input, fails if the pin is already in use.
+    ///
+    /// Nothing is read from the IC, current configuration is not taken into account.
+    pub(crate) async fn init_ignore_mode(
+        usb4604: Usb4604,
+        pin: &'static PinDescriptor,
+    ) -> Result<Flex, Error> {
+        let id = usb4604.claim_pin(PinState {
+            pin,
+            is_out_en: false,
+            level: None,
+            pull: None,
+        })?;
+        // returned before any reads, so that the claim is released on error
+        Ok(Flex {
             usb4604,
-            pio,
+            pin,
             mode: Mode::Input,
             is_out_en: false,
+            id,
+            drop_policy: DropPolicy::Keep,
+            restore: None,
+        })
+    }
+
+    /// Choose what happens to the pin when this handle is dropped, [DropPolicy::Keep] by default.
+    ///
+    /// [DropPolicy::Restore] reads the current configuration of the pin the first time it is chosen,
+    /// the other policies don't touch the IC.
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) -> Result<(), Error> {
+        block_on(self.set_drop_policy_async(policy))
+    }
+
+    /// Async version of [set_drop_policy](Self::set_drop_policy).
+    pub async fn set_drop_policy_async(&mut self, policy: DropPolicy) -> Result<(), Error> {
+        if policy == DropPolicy::Restore && self.restore.is_none() {
+            let (usb4604, pin) = (&self.usb4604, self.pin);
+            let regs = pin.registers;
+            self.restore = Some(Snapshot {
+                is_out: read_bit(usb4604, regs.dir, pin).await?,
+                output: read_bit(usb4604, regs.output, pin).await?,
+                pull_up: read_bit(usb4604, regs.pull_up, pin).await?,
+                pull_down: read_bit(usb4604, regs.pull_down, pin).await?,
+            });
         }
+        self.drop_policy = policy;
+        Ok(())
+    }
+
+    /// Returns what happens to the pin when this handle is dropped.
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.drop_policy
     }
 
     /// Set initial level and put the pin into push-pull output mode.
     pub fn set_as_output(&mut self, initial: Option<Level>) -> Result<(), Error> {
+        block_on(self.set_as_output_async(initial))
+    }
+
+    /// Async version of [set_as_output](Self::set_as_output).
+    pub async fn set_as_output_async(&mut self, initial: Option<Level>) -> Result<(), Error> {
         if let Some(level) = initial {
-            self.set_level(level)?;
+            self.set_level_async(level).await?;
         }
-        self.set_mode(Mode::OutputPushPull)?;
-        self.set_pull(Pull::None)?;
+        self.set_mode(Mode::OutputPushPull).await?;
+        self.set_pull_async(Pull::None).await?;
         self.mode = Mode::OutputPushPull;
         Ok(())
     }
 
     /// Consume self, optionally set initial level and put the pin into push-pull output mode, return [PushPullOutput].
-    pub fn into_output(mut self, initial: Option<Level>) -> Result<PushPullOutput, Error> {
-        self.set_as_output(initial)?;
+    pub fn into_output(self, initial: Option<Level>) -> Result<PushPullOutput, Error> {
+        block_on(self.into_output_async(initial))
+    }
+
+    /// Async version of [into_output](Self::into_output).
+    pub async fn into_output_async(
+        mut self,
+        initial: Option<Level>,
+    ) -> Result<PushPullOutput, Error> {
+        self.set_as_output_async(initial).await?;
         Ok(PushPullOutput { flex: self })
     }
 
@@ -118,8 +297,13 @@ impl Flex {
     ///
     /// The internal pull-up or pull-down resistor can optionally be enabled according to pull.
     pub fn set_as_input(&mut self, pull: Pull) -> Result<(), Error> {
-        self.set_mode(Mode::Input)?;
-        self.set_pull(pull)?;
+        block_on(self.set_as_input_async(pull))
+    }
+
+    /// Async version of [set_as_input](Self::set_as_input).
+    pub async fn set_as_input_async(&mut self, pull: Pull) -> Result<(), Error> {
+        self.set_mode(Mode::Input).await?;
+        self.set_pull_async(pull).await?;
         self.mode = Mode::Input;
         Ok(())
     }
@@ -127,8 +311,13 @@ impl Flex {
     /// Consume self, put the pin into input mode and return [Input].
     ///
     /// The internal pull-up or pull-down resistor can optionally be enabled according to pull.
-    pub fn into_input(mut self, pull: Pull) -> Result<Input, Error> {
-        self.set_as_input(pull)?;
+    pub fn into_input(self, pull: Pull) -> Result<Input, Error> {
+        block_on(self.into_input_async(pull))
+    }
+
+    /// Async version of [into_input](Self::into_input).
+    pub async fn into_input_async(mut self, pull: Pull) -> Result<Input, Error> {
+        self.set_as_input_async(pull).await?;
         Ok(Input { flex: self })
     }
 
@@ -139,8 +328,13 @@ impl Flex {
     ///
     /// The internal pull-up or pull-down resistor can optionally be enabled according to pull.
     pub fn set_as_open_drain(&mut self, pull: Pull) -> Result<(), Error> {
-        self.set_mode(Mode::Input)?;
-        self.set_pull(pull)?;
+        block_on(self.set_as_open_drain_async(pull))
+    }
+
+    /// Async version of [set_as_open_drain](Self::set_as_open_drain).
+    pub async fn set_as_open_drain_async(&mut self, pull: Pull) -> Result<(), Error> {
+        self.set_mode(Mode::Input).await?;
+        self.set_pull_async(pull).await?;
         self.mode = Mode::OutputOpenDrain;
         Ok(())
     }
@@ -151,91 +345,62 @@ impl Flex {
     /// in which case you can read the input to figure out whether another device is driving the line low.
     ///
     /// The internal pull-up or pull-down resistor can optionally be enabled according to pull.
-    pub fn into_open_drain_output(mut self, pull: Pull) -> Result<OpenDrainOutput, Error> {
-        self.set_as_open_drain(pull)?;
+    pub fn into_open_drain_output(self, pull: Pull) -> Result<OpenDrainOutput, Error> {
+        block_on(self.into_open_drain_output_async(pull))
+    }
+
+    /// Async version of [into_open_drain_output](Self::into_open_drain_output).
+    pub async fn into_open_drain_output_async(
+        mut self,
+        pull: Pull,
+    ) -> Result<OpenDrainOutput, Error> {
+        self.set_as_open_drain_async(pull).await?;
         Ok(OpenDrainOutput { flex: self })
     }
 
-    fn set_mode(&mut self, mode: Mode) -> Result<(), Error> {
+    async fn set_mode(&mut self, mode: Mode) -> Result<(), Error> {
         let out_en = matches!(mode, Mode::OutputPushPull);
         self.is_out_en = out_en;
-        match self.pio {
-            Pio::Pio0 => self
-                .usb4604
-                .modify_reg::<Gpio0_7Dir, _>(|r| r.set_gpio0_out_en(out_en)),
-            Pio::Pio1 => self
-                .usb4604
-                .modify_reg::<Gpio0_7Dir, _>(|r| r.set_gpio1_out_en(out_en)),
-            Pio::Pio3 => self
-                .usb4604
-                .modify_reg::<Gpio0_7Dir, _>(|r| r.set_gpio3_out_en(out_en)),
-            Pio::Pio8 => self
-                .usb4604
-                .modify_reg::<Gpio8_10Dir, _>(|r| r.set_gpio8_out_en(out_en)),
-            Pio::Pio9 => self
-                .usb4604
-                .modify_reg::<Gpio8_10Dir, _>(|r| r.set_gpio9_out_en(out_en)),
-            Pio::Pio10 => self
-                .usb4604
-                .modify_reg::<Gpio8_10Dir, _>(|r| r.set_gpio10_out_en(out_en)),
-            Pio::Pio19 => self
-                .usb4604
-                .modify_reg::<Gpio17_20Dir, _>(|r| r.set_gpio19_out_en(out_en)),
-            Pio::Pio20 => self
-                .usb4604
-                .modify_reg::<Gpio17_20Dir, _>(|r| r.set_gpio20_out_en(out_en)),
-        }?;
+        write_dir(&mut self.usb4604, self.pin, out_en).await?;
+        self.usb4604.update_pin(self.id, |s| s.is_out_en = out_en);
         Ok(())
     }
 
     /// Returns current pin mode.
     /// When created as Flex, pin mode is read out from the IC, which persists until hub reset.
+    /// See [Usb4604::set_reconnect] to restore pin configuration automatically after a reset.
     pub fn mode(&self) -> Mode {
         self.mode
     }
 
-    /// Returns GPIO number that this Flex is using.
-    pub fn pio(&self) -> Pio {
-        self.pio
+    /// Returns GPIO number that this Flex is using, or None if the pin is not in [Pio],
+    /// i.e. was opened with [Usb4604::gpio_extended], see [extended_pio](Self::extended_pio).
+    pub fn pio(&self) -> Option<Pio> {
+        self.pin.pio.verified()
+    }
+
+    /// Returns GPIO number that this Flex is using, works for every pin.
+    pub fn extended_pio(&self) -> ExtendedPio {
+        self.pin.pio
     }
 
     /// Set the output level.
     pub fn set_level(&mut self, level: Level) -> Result<(), Error> {
+        block_on(self.set_level_async(level))
+    }
+
+    /// Async version of [set_level](Self::set_level).
+    pub async fn set_level_async(&mut self, level: Level) -> Result<(), Error> {
         if self.mode == Mode::OutputOpenDrain {
             if level == Level::High {
-                self.set_mode(Mode::Input)?;
+                self.set_mode(Mode::Input).await?;
                 return Ok(());
             } else {
-                self.set_mode(Mode::OutputPushPull)?;
+                self.set_mode(Mode::OutputPushPull).await?;
             }
         }
-        let is_high = matches!(level, Level::High);
-        match self.pio {
-            Pio::Pio0 => self
-                .usb4604
-                .modify_reg::<Gpio0_7Output, _>(|r| r.set_gpio0_out(is_high)),
-            Pio::Pio1 => self
-                .usb4604
-                .modify_reg::<Gpio0_7Output, _>(|r| r.set_gpio1_out(is_high)),
-            Pio::Pio3 => self
-                .usb4604
-                .modify_reg::<Gpio0_7Output, _>(|r| r.set_gpio3_out(is_high)),
-            Pio::Pio8 => self
-                .usb4604
-                .modify_reg::<Gpio8_10Output, _>(|r| r.set_gpio8_out(is_high)),
-            Pio::Pio9 => self
-                .usb4604
-                .modify_reg::<Gpio8_10Output, _>(|r| r.set_gpio9_out(is_high)),
-            Pio::Pio10 => self
-                .usb4604
-                .modify_reg::<Gpio8_10Output, _>(|r| r.set_gpio10_out(is_high)),
-            Pio::Pio19 => self
-                .usb4604
-                .modify_reg::<Gpio17_20Output, _>(|r| r.set_gpio19_out(is_high)),
-            Pio::Pio20 => self
-                .usb4604
-                .modify_reg::<Gpio17_20Output, _>(|r| r.set_gpio20_out(is_high)),
-        }?;
+        write_output(&mut self.usb4604, self.pin, level).await?;
+        self.usb4604.update_pin(self.id, |s| s.level = Some(level));
         Ok(())
     }
 
@@ -245,32 +410,52 @@ impl Flex {
         self.set_level(Level::High)
     }
 
+    /// Async version of [set_high](Self::set_high).
+    pub async fn set_high_async(&mut self) -> Result<(), Error> {
+        self.set_level_async(Level::High).await
+    }
+
     /// Set the output as low.
     pub fn set_low(&mut self) -> Result<(), Error> {
         self.set_level(Level::Low)
     }
 
+    /// Async version of [set_low](Self::set_low).
+    pub async fn set_low_async(&mut self) -> Result<(), Error> {
+        self.set_level_async(Level::Low).await
+    }
+
     /// Toggle the output level.
     pub fn toggle(&mut self) -> Result<(), Error> {
-        if self.get_output_level()? == Level::Low {
-            self.set_level(Level::High)
+        block_on(self.toggle_async())
+    }
+
+    /// Async version of [toggle](Self::toggle).
+    pub async fn toggle_async(&mut self) -> Result<(), Error> {
+        if self.get_output_level_async().await? == Level::Low {
+            self.set_level_async(Level::High).await
         } else {
-            self.set_level(Level::Low)
+            self.set_level_async(Level::Low).await
         }
     }
 
     /// Get output level, previously set with [set_level](Self::set_level).
     pub fn get_output_level(&self) -> Result<Level, Error> {
-        let is_high = match self.pio {
-            Pio::Pio0 => self.usb4604.read_reg::<Gpio0_7Output>()?.gpio0_out(),
-            Pio::Pio1 => self.usb4604.read_reg::<Gpio0_7Output>()?.gpio1_out(),
-            Pio::Pio3 => self.usb4604.read_reg::<Gpio0_7Output>()?.gpio3_out(),
-            Pio::Pio8 => self.usb4604.read_reg::<Gpio8_10Output>()?.gpio8_out(),
-            Pio::Pio9 => self.usb4604.read_reg::<Gpio8_10Output>()?.gpio9_out(),
-            Pio::Pio10 => self.usb4604.read_reg::<Gpio8_10Output>()?.gpio10_out(),
-            Pio::Pio19 => self.usb4604.read_reg::<Gpio17_20Output>()?.gpio19_out(),
-            Pio::Pio20 => self.usb4604.read_reg::<Gpio17_20Output>()?.gpio20_out(),
-        };
+        block_on(self.get_output_level_async())
+    }
+
+    /// Async version of [get_output_level](Self::get_output_level).
+    pub async fn get_output_level_async(&self) -> Result<Level, Error> {
+        if self.mode == Mode::OutputOpenDrain {
+            // output register is not touched when released, only direction is
+            let level = if self.is_out_en {
+                Level::Low
+            } else {
+                Level::High
+            };
+            return Ok(level);
+        }
+        let is_high = read_bit(&self.usb4604, self.pin.registers.output, self.pin).await?;
         let level = if is_high { Level::High } else { Level::Low };
         Ok(level)
     }
@@ -280,23 +465,29 @@ impl Flex {
         Ok(self.get_output_level()? == Level::High)
     }
 
+    /// Async version of [is_set_high](Self::is_set_high).
+    pub async fn is_set_high_async(&self) -> Result<bool, Error> {
+        Ok(self.get_output_level_async().await? == Level::High)
+    }
+
     /// Returns true, if output was previously set to low with [set_level](Self::set_level).
     pub fn is_set_low(&self) -> Result<bool, Error> {
         Ok(self.get_output_level()? == Level::Low)
     }
 
+    /// Async version of [is_set_low](Self::is_set_low).
+    pub async fn is_set_low_async(&self) -> Result<bool, Error> {
+        Ok(self.get_output_level_async().await? == Level::Low)
+    }
+
     /// Get pin input level.
     pub fn get_input_level(&self) -> Result<Level, Error> {
-        let is_high = match self.pio {
-            Pio::Pio0 => self.usb4604.read_reg::<Gpio0_7Input>()?.gpio0_in(),
-            Pio::Pio1 => self.usb4604.read_reg::<Gpio0_7Input>()?.gpio1_in(),
-            Pio::Pio3 => self.usb4604.read_reg::<Gpio0_7Input>()?.gpio3_in(),
-            Pio::Pio8 => self.usb4604.read_reg::<Gpio8_10Input>()?.gpio8_in(),
-            Pio::Pio9 => self.usb4604.read_reg::<Gpio8_10Input>()?.gpio9_in(),
-            Pio::Pio10 => self.usb4604.read_reg::<Gpio8_10Input>()?.gpio10_in(),
-            Pio::Pio19 => self.usb4604.read_reg::<Gpio17_20Input>()?.gpio19_in(),
-            Pio::Pio20 => self.usb4604.read_reg::<Gpio17_20Input>()?.gpio20_in(),
-        };
+        block_on(self.get_input_level_async())
+    }
+
+    /// Async version of [get_input_level](Self::get_input_level).
+    pub async fn get_input_level_async(&self) -> Result<Level, Error> {
+        let is_high = read_bit(&self.usb4604, self.pin.registers.input, self.pin).await?;
         let level = if is_high { Level::High } else { Level::Low };
         Ok(level)
     }
@@ -306,70 +497,188 @@ impl Flex {
         Ok(self.get_input_level()? == Level::High)
     }
 
+    /// Async version of [is_high](Self::is_high).
+    pub async fn is_high_async(&self) -> Result<bool, Error> {
+        Ok(self.get_input_level_async().await? == Level::High)
+    }
+
     /// Returns true if pin input level is low.
     pub fn is_low(&self) -> Result<bool, Error> {
-        Ok(self.get_input_level()? == Level::High)
+        Ok(self.get_input_level()? == Level::Low)
+    }
+
+    /// Async version of [is_low](Self::is_low).
+    pub async fn is_low_async(&self) -> Result<bool, Error> {
+        Ok(self.get_input_level_async().await? == Level::Low)
     }
 
     /// Enable or disable pull-up or pull-down resistor.
     pub fn set_pull(&mut self, pull: Pull) -> Result<(), Error> {
-        let (pull_up, pull_down) = match pull {
-            Pull::None => (false, false),
-            Pull::Up => (true, false),
-            Pull::Down => (false, true),
+        block_on(self.set_pull_async(pull))
+    }
+
+    /// Async version of [set_pull](Self::set_pull).
+    pub async fn set_pull_async(&mut self, pull: Pull) -> Result<(), Error> {
+        write_pull(&mut self.usb4604, self.pin, pull).await?;
+        self.usb4604.update_pin(self.id, |s| s.pull = Some(pull));
+        Ok(())
+    }
+
+    pub(crate) fn descriptor(&self) -> &'static PinDescriptor {
+        self.pin
+    }
+
+    pub(crate) fn usb4604(&self) -> &Usb4604 {
+        &self.usb4604
+    }
+
+    /// Keep track of direction written on behalf of this pin by [PinGroup](crate::PinGroup).
+    pub(crate) fn record_dir(&mut self, mode: Mode, is_out_en: bool) {
+        self.mode = mode;
+        self.is_out_en = is_out_en;
+        self.usb4604
+            .update_pin(self.id, |s| s.is_out_en = is_out_en);
+    }
+
+    /// Keep track of output level written on behalf of this pin by [PinGroup](crate::PinGroup).
+    pub(crate) fn record_level(&mut self, level: Level) {
+        self.usb4604.update_pin(self.id, |s| s.level = Some(level));
+    }
+
+    /// Keep track of pull written on behalf of this pin by [PinGroup](crate::PinGroup).
+    pub(crate) fn record_pull(&mut self, pull: Pull) {
+        self.usb4604.update_pin(self.id, |s| s.pull = Some(pull));
+    }
+}
+
+impl Drop for Flex {
+    fn drop(&mut self) {
+        if self.drop_policy == DropPolicy::Keep {
+            self.usb4604.release_pin(self.id);
+            return;
+        }
+        let mut usb4604 = self.usb4604.clone();
+        let (pin, policy, restore, id) = (self.pin, self.drop_policy, self.restore, self.id);
+        let apply = async move {
+            // nowhere to report an error to, the pin is released regardless
+            let _ = apply_drop_policy(&mut usb4604, pin, policy, restore).await;
+            usb4604.release_pin(id);
         };
-        match self.pio {
-            Pio::Pio0 => {
-                self.usb4604
-                    .modify_reg::<Gpio0_7PullUp, _>(|r| r.set_gpio0_pu(pull_up))?;
-                self.usb4604
-                    .modify_reg::<Gpio0_7PullDown, _>(|r| r.set_gpio0_pd(pull_down))?;
-            }
-            Pio::Pio1 => {
-                self.usb4604
-                    .modify_reg::<Gpio0_7PullUp, _>(|r| r.set_gpio1_pu(pull_up))?;
-                self.usb4604
-                    .modify_reg::<Gpio0_7PullDown, _>(|r| r.set_gpio1_pd(pull_down))?;
-            }
-            Pio::Pio3 => {
-                self.usb4604
-                    .modify_reg::<Gpio0_7PullUp, _>(|r| r.set_gpio3_pu(pull_up))?;
-                self.usb4604
-                    .modify_reg::<Gpio0_7PullDown, _>(|r| r.set_gpio3_pd(pull_down))?;
-            }
-            Pio::Pio8 => {
-                self.usb4604
-                    .modify_reg::<Gpio8_10PullUp, _>(|r| r.set_gpio8_pu(pull_up))?;
-                self.usb4604
-                    .modify_reg::<Gpio8_10PullDown, _>(|r| r.set_gpio8_pd(pull_down))?;
-            }
-            Pio::Pio9 => {
-                self.usb4604
-                    .modify_reg::<Gpio8_10PullUp, _>(|r| r.set_gpio9_pu(pull_up))?;
-                self.usb4604
-                    .modify_reg::<Gpio8_10PullDown, _>(|r| r.set_gpio9_pd(pull_down))?;
-            }
-            Pio::Pio10 => {
-                self.usb4604
-                    .modify_reg::<Gpio8_10PullUp, _>(|r| r.set_gpio10_pu(pull_up))?;
-                self.usb4604
-                    .modify_reg::<Gpio8_10PullDown, _>(|r| r.set_gpio10_pd(pull_down))?;
-            }
-            Pio::Pio19 => {
-                self.usb4604
-                    .modify_reg::<Gpio17_20PullUp, _>(|r| r.set_gpio19_pu(pull_up))?;
-                self.usb4604
-                    .modify_reg::<Gpio17_20PullDown, _>(|r| r.set_gpio19_pd(pull_down))?;
-            }
-            Pio::Pio20 => {
-                self.usb4604
-                    .modify_reg::<Gpio17_20PullUp, _>(|r| r.set_gpio20_pu(pull_up))?;
-                self.usb4604
-                    .modify_reg::<Gpio17_20PullDown, _>(|r| r.set_gpio20_pd(pull_down))?;
-            }
+        // the holder might be a task paused on this very thread, waiting for it here would never end
+        if self.usb4604.is_register_lock_held() {
+            thread::spawn(move || block_on(apply));
+        } else {
+            block_on(apply);
+        }
+    }
+}
+
+/// Write the configuration a dropped pin is left in, handle state is not updated as it is going away.
+async fn apply_drop_policy(
+    usb4604: &mut Usb4604,
+    pin: &'static PinDescriptor,
+    policy: DropPolicy,
+    restore: Option<Snapshot>,
+) -> Result<(), Error> {
+    match policy {
+        DropPolicy::Keep => Ok(()),
+        DropPolicy::Input => PinState::input(pin, Pull::None).replay(usb4604).await,
+        DropPolicy::Restore => {
+            // always read when the policy was chosen
+            let Some(snapshot) = restore else {
+                return Ok(());
+            };
+            let regs = pin.registers;
+            // output and pulls first, so that a pin that was an output returns straight to its original level
+            write_bit(usb4604, regs.output, pin, snapshot.output).await?;
+            write_bit(usb4604, regs.pull_up, pin, snapshot.pull_up).await?;
+            write_bit(usb4604, regs.pull_down, pin, snapshot.pull_down).await?;
+            write_bit(usb4604, regs.dir, pin, snapshot.is_out).await
+        }
+        DropPolicy::Drive(level) => PinState::output(pin, level).replay(usb4604).await,
+    }
+}
+
+impl PinState {
+    /// Push-pull output driving level, pulls disabled.
+    pub(crate) fn output(pin: &'static PinDescriptor, level: Level) -> PinState {
+        PinState {
+            pin,
+            is_out_en: true,
+            level: Some(level),
+            pull: Some(Pull::None),
+        }
+    }
+
+    /// Input with the given pull, output level left as it is.
+    pub(crate) fn input(pin: &'static PinDescriptor, pull: Pull) -> PinState {
+        PinState {
+            pin,
+            is_out_en: false,
+            level: None,
+            pull: Some(pull),
         }
-        Ok(())
     }
+
+    pub(crate) fn pin(&self) -> &'static PinDescriptor {
+        self.pin
+    }
+
+    /// Apply configuration again, e.g. after the hub was reset.
+    pub(crate) async fn replay(&self, usb4604: &mut Usb4604) -> Result<(), Error> {
+        // level and pull first, so that the pin does not glitch when it becomes an output
+        if let Some(level) = self.level {
+            write_output(usb4604, self.pin, level).await?;
+        }
+        if let Some(pull) = self.pull {
+            write_pull(usb4604, self.pin, pull).await?;
+        }
+        write_dir(usb4604, self.pin, self.is_out_en).await
+    }
+}
+
+async fn write_dir(usb4604: &mut Usb4604, pin: &PinDescriptor, out_en: bool) -> Result<(), Error> {
+    write_bit(usb4604, pin.registers.dir, pin, out_en).await
+}
+
+async fn write_output(
+    usb4604: &mut Usb4604,
+    pin: &PinDescriptor,
+    level: Level,
+) -> Result<(), Error> {
+    write_bit(usb4604, pin.registers.output, pin, level == Level::High).await
+}
+
+async fn write_pull(usb4604: &mut Usb4604, pin: &PinDescriptor, pull: Pull) -> Result<(), Error> {
+    let (pull_up, pull_down) = match pull {
+        Pull::None => (false, false),
+        Pull::Up => (true, false),
+        Pull::Down => (false, true),
+    };
+    write_bit(usb4604, pin.registers.pull_up, pin, pull_up).await?;
+    write_bit(usb4604, pin.registers.pull_down, pin, pull_down).await
+}
+
+async fn read_bit(usb4604: &Usb4604, addr: u16, pin: &PinDescriptor) -> Result<bool, Error> {
+    Ok(usb4604.read_addr_async(addr).await? & pin.mask() != 0)
+}
+
+async fn write_bit(
+    usb4604: &mut Usb4604,
+    addr: u16,
+    pin: &PinDescriptor,
+    value: bool,
+) -> Result<(), Error> {
+    usb4604
+        .modify_addr_async(addr, |bits| {
+            if value {
+                bits | pin.mask()
+            } else {
+                bits & !pin.mask()
+            }
+        })
+        .await?;
+    Ok(())
 }
 
 impl PushPullOutput {
@@ -378,29 +687,74 @@ impl PushPullOutput {
         self.flex.set_high()
     }
 
+    /// Async version of [set_high](Self::set_high).
+    pub async fn set_high_async(&mut self) -> Result<(), Error> {
+        self.flex.set_high_async().await
+    }
+
     /// Set the output as low.
     pub fn set_low(&mut self) -> Result<(), Error> {
         self.flex.set_low()
     }
 
+    /// Async version of [set_low](Self::set_low).
+    pub async fn set_low_async(&mut self) -> Result<(), Error> {
+        self.flex.set_low_async().await
+    }
+
     /// Toggle the output level.
     pub fn toggle(&mut self) -> Result<(), Error> {
         self.flex.toggle()
     }
 
+    /// Async version of [toggle](Self::toggle).
+    pub async fn toggle_async(&mut self) -> Result<(), Error> {
+        self.flex.toggle_async().await
+    }
+
     /// Set the output level.
     pub fn set_level(&mut self, level: Level) -> Result<(), Error> {
         self.flex.set_level(level)
     }
 
+    /// Async version of [set_level](Self::set_level).
+    pub async fn set_level_async(&mut self, level: Level) -> Result<(), Error> {
+        self.flex.set_level_async(level).await
+    }
+
     /// Get previously set output level.
     pub fn level(&self) -> Result<Level, Error> {
         self.flex.get_output_level()
     }
 
-    /// Returns GPIO number that this output is using.
-    pub fn pio(&self) -> Pio {
-        self.flex.pio
+    /// Async version of [level](Self::level).
+    pub async fn level_async(&self) -> Result<Level, Error> {
+        self.flex.get_output_level_async().await
+    }
+
+    /// Returns GPIO number that this output is using, or None if it is not in [Pio], see [Flex::pio].
+    pub fn pio(&self) -> Option<Pio> {
+        self.flex.pio()
+    }
+
+    /// Returns GPIO number that this output is using, works for every pin.
+    pub fn extended_pio(&self) -> ExtendedPio {
+        self.flex.extended_pio()
+    }
+
+    /// Choose what happens to the pin when this handle is dropped, see [Flex::set_drop_policy].
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) -> Result<(), Error> {
+        self.flex.set_drop_policy(policy)
+    }
+
+    /// Async version of [set_drop_policy](Self::set_drop_policy).
+    pub async fn set_drop_policy_async(&mut self, policy: DropPolicy) -> Result<(), Error> {
+        self.flex.set_drop_policy_async(policy).await
+    }
+
+    /// Returns what happens to the pin when this handle is dropped.
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.flex.drop_policy()
     }
 }
 
@@ -410,20 +764,55 @@ impl Input {
         self.flex.is_high()
     }
 
+    /// Async version of [is_high](Self::is_high).
+    pub async fn is_high_async(&self) -> Result<bool, Error> {
+        self.flex.is_high_async().await
+    }
+
     /// Returns true if pin input level is low.
     pub fn is_low(&self) -> Result<bool, Error> {
         self.flex.is_low()
     }
 
-    /// Returns GPIO number that this input is using.
-    pub fn pio(&self) -> Pio {
-        self.flex.pio
+    /// Async version of [is_low](Self::is_low).
+    pub async fn is_low_async(&self) -> Result<bool, Error> {
+        self.flex.is_low_async().await
+    }
+
+    /// Returns GPIO number that this input is using, or None if it is not in [Pio], see [Flex::pio].
+    pub fn pio(&self) -> Option<Pio> {
+        self.flex.pio()
+    }
+
+    /// Returns GPIO number that this input is using, works for every pin.
+    pub fn extended_pio(&self) -> ExtendedPio {
+        self.flex.extended_pio()
+    }
+
+    /// Choose what happens to the pin when this handle is dropped, see [Flex::set_drop_policy].
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) -> Result<(), Error> {
+        self.flex.set_drop_policy(policy)
+    }
+
+    /// Async version of [set_drop_policy](Self::set_drop_policy).
+    pub async fn set_drop_policy_async(&mut self, policy: DropPolicy) -> Result<(), Error> {
+        self.flex.set_drop_policy_async(policy).await
+    }
+
+    /// Returns what happens to the pin when this handle is dropped.
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.flex.drop_policy()
     }
 
     /// Enable or disable pull-up or pull-down resistor.
     pub fn set_pull(&mut self, pull: Pull) -> Result<(), Error> {
         self.flex.set_pull(pull)
     }
+
+    /// Async version of [set_pull](Self::set_pull).
+    pub async fn set_pull_async(&mut self, pull: Pull) -> Result<(), Error> {
+        self.flex.set_pull_async(pull).await
+    }
 }
 
 impl OpenDrainOutput {
@@ -432,33 +821,243 @@ impl OpenDrainOutput {
         self.flex.set_high()
     }
 
+    /// Async version of [set_high_z](Self::set_high_z).
+    pub async fn set_high_z_async(&mut self) -> Result<(), Error> {
+        self.flex.set_high_async().await
+    }
+
     /// Set the output as low.
     pub fn set_low(&mut self) -> Result<(), Error> {
         self.flex.set_low()
     }
 
+    /// Async version of [set_low](Self::set_low).
+    pub async fn set_low_async(&mut self) -> Result<(), Error> {
+        self.flex.set_low_async().await
+    }
+
     /// Toggle the output level between high-z and low.
     pub fn toggle(&mut self) -> Result<(), Error> {
         self.flex.toggle()
     }
 
+    /// Async version of [toggle](Self::toggle).
+    pub async fn toggle_async(&mut self) -> Result<(), Error> {
+        self.flex.toggle_async().await
+    }
+
     /// Set the output level.
     pub fn set_level(&mut self, level: Level) -> Result<(), Error> {
         self.flex.set_level(level)
     }
 
+    /// Async version of [set_level](Self::set_level).
+    pub async fn set_level_async(&mut self, level: Level) -> Result<(), Error> {
+        self.flex.set_level_async(level).await
+    }
+
     /// If previously set as low, return Level::Low, otherwise read input level and return it.
     pub fn level(&self) -> Result<Level, Error> {
+        block_on(self.level_async())
+    }
+
+    /// Async version of [level](Self::level).
+    pub async fn level_async(&self) -> Result<Level, Error> {
         if self.flex.is_out_en {
             Ok(Level::Low)
         } else {
-            self.flex.get_input_level()
+            self.flex.get_input_level_async().await
         }
     }
 
-    /// Returns GPIO number that this open drain pin is using.
-    pub fn pio(&self) -> Pio {
-        self.flex.pio
+    /// Returns GPIO number that this open drain pin is using, or None if it is not in [Pio], see [Flex::pio].
+    pub fn pio(&self) -> Option<Pio> {
+        self.flex.pio()
+    }
+
+    /// Returns GPIO number that this open drain pin is using, works for every pin.
+    pub fn extended_pio(&self) -> ExtendedPio {
+        self.flex.extended_pio()
+    }
+
+    /// Choose what happens to the pin when this handle is dropped, see [Flex::set_drop_policy].
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) -> Result<(), Error> {
+        self.flex.set_drop_policy(policy)
+    }
+
+    /// Async version of [set_drop_policy](Self::set_drop_policy).
+    pub async fn set_drop_policy_async(&mut self, policy: DropPolicy) -> Result<(), Error> {
+        self.flex.set_drop_policy_async(policy).await
+    }
+
+    /// Returns what happens to the pin when this handle is dropped.
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.flex.drop_policy()
+    }
+}
+
+impl ErrorType for Flex {
+    type Error = Error;
+}
+
+impl OutputPin for Flex {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Flex::set_low(self)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Flex::set_high(self)
+    }
+}
+
+impl StatefulOutputPin for Flex {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        Flex::is_set_high(self)
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Flex::is_set_low(self)
+    }
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        Flex::toggle(self)
+    }
+}
+
+impl InputPin for Flex {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Flex::is_high(self)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Flex::is_low(self)
+    }
+}
+
+/// Input registers are polled, see [Usb4604::set_poll_interval].
+impl Wait for Flex {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        wait_for(&self.usb4604, self.pin, Condition::High).await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        wait_for(&self.usb4604, self.pin, Condition::Low).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        wait_for(&self.usb4604, self.pin, Condition::RisingEdge).await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        wait_for(&self.usb4604, self.pin, Condition::FallingEdge).await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        wait_for(&self.usb4604, self.pin, Condition::AnyEdge).await
+    }
+}
+
+/// Input registers are polled, see [Usb4604::set_poll_interval].
+impl Wait for Input {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.flex.wait_for_high().await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.flex.wait_for_low().await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.flex.wait_for_rising_edge().await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.flex.wait_for_falling_edge().await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.flex.wait_for_any_edge().await
+    }
+}
+
+impl ErrorType for PushPullOutput {
+    type Error = Error;
+}
+
+impl OutputPin for PushPullOutput {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        PushPullOutput::set_low(self)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        PushPullOutput::set_high(self)
+    }
+}
+
+impl StatefulOutputPin for PushPullOutput {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        self.flex.is_set_high()
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        self.flex.is_set_low()
+    }
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        PushPullOutput::toggle(self)
+    }
+}
+
+impl ErrorType for Input {
+    type Error = Error;
+}
+
+impl InputPin for Input {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Input::is_high(self)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Input::is_low(self)
+    }
+}
+
+impl ErrorType for OpenDrainOutput {
+    type Error = Error;
+}
+
+/// High means high-z, line level then depends on pull-up resistors and other devices, use [InputPin] to read it.
+impl OutputPin for OpenDrainOutput {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        OpenDrainOutput::set_low(self)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set_high_z()
+    }
+}
+
+impl StatefulOutputPin for OpenDrainOutput {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        self.flex.is_set_high()
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        self.flex.is_set_low()
+    }
+
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        OpenDrainOutput::toggle(self)
+    }
+}
+
+impl InputPin for OpenDrainOutput {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        self.flex.is_high()
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        self.flex.is_low()
     }
 }
 
@@ -0,0 +1,235 @@
+use crate::blocking::block_on;
+use crate::{Error, Flex, Level, Mode, Pio, Pull, Usb4604};
+
+/// Several pins that are configured, written and read together, bit n of a value corresponds to n-th pin.
+///
+/// Every register is read-modified-written only once per call, no matter how many of its pins are in the group,
+/// so pins of the same bank change at the same time. Banks are updated one after another, in the order in which
+/// they first appear in the group. When pins are also switched to outputs, output levels are written first.
+pub struct PinGroup {
+    usb4604: Usb4604,
+    pins: Vec<Flex>,
+}
+
+impl PinGroup {
+    /// Group up to 32 pins of the same device.
+    pub fn new(pins: Vec<Flex>) -> Result<PinGroup, Error> {
+        let Some(first) = pins.first() else {
+            return Err(Error::Other("pin group is empty"));
+        };
+        if pins.len() > 32 {
+            return Err(Error::Other("pin group can have at most 32 pins"));
+        }
+        for (i, pin) in pins.iter().enumerate() {
+            if !pin.usb4604().is_same_device(first.usb4604()) {
+                return Err(Error::Other(
+                    "pins of a group must belong to the same device",
+                ));
+            }
+            if pins[..i]
+                .iter()
+                .any(|other| other.extended_pio() == pin.extended_pio())
+            {
+                return Err(Error::Other("pin is in the group twice"));
+            }
+        }
+        Ok(PinGroup {
+            usb4604: first.usb4604().clone(),
+            pins,
+        })
+    }
+
+    /// Pins of the group, in bit order.
+    pub fn pins(&self) -> &[Flex] {
+        &self.pins
+    }
+
+    /// Consume self and return the pins back.
+    pub fn into_pins(self) -> Vec<Flex> {
+        self.pins
+    }
+
+    /// Number of pins in the group.
+    pub fn len(&self) -> usize {
+        self.pins.len()
+    }
+
+    /// Always false, empty groups can't be created.
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// Set initial levels and put all pins into push-pull output mode.
+    pub fn set_as_output(&mut self, initial: u32) -> Result<(), Error> {
+        block_on(self.set_as_output_async(initial))
+    }
+
+    /// Async version of [set_as_output](Self::set_as_output).
+    pub async fn set_as_output_async(&mut self, initial: u32) -> Result<(), Error> {
+        let mut output = Batch::default();
+        let mut dir = Batch::default();
+        let mut pull = Batch::default();
+        for (n, pin) in self.pins.iter().enumerate() {
+            let d = pin.descriptor();
+            output.set(d.registers.output, d.mask(), bit(initial, n));
+            dir.set(d.registers.dir, d.mask(), true);
+            pull.set(d.registers.pull_up, d.mask(), false);
+            pull.set(d.registers.pull_down, d.mask(), false);
+        }
+        output.apply(&self.usb4604).await?;
+        for (n, pin) in self.pins.iter_mut().enumerate() {
+            pin.record_level(Level::from(bit(initial, n)));
+        }
+        dir.apply(&self.usb4604).await?;
+        for pin in &mut self.pins {
+            pin.record_dir(Mode::OutputPushPull, true);
+        }
+        pull.apply(&self.usb4604).await?;
+        for pin in &mut self.pins {
+            pin.record_pull(Pull::None);
+        }
+        Ok(())
+    }
+
+    /// Put all pins into input mode, with the same pull-up or pull-down resistor configuration.
+    pub fn set_as_input(&mut self, pull: Pull) -> Result<(), Error> {
+        block_on(self.set_as_input_async(pull))
+    }
+
+    /// Async version of [set_as_input](Self::set_as_input).
+    pub async fn set_as_input_async(&mut self, pull: Pull) -> Result<(), Error> {
+        let mut dir = Batch::default();
+        let mut pulls = Batch::default();
+        for pin in &self.pins {
+            let d = pin.descriptor();
+            dir.set(d.registers.dir, d.mask(), false);
+            pulls.set(d.registers.pull_up, d.mask(), pull == Pull::Up);
+            pulls.set(d.registers.pull_down, d.mask(), pull == Pull::Down);
+        }
+        dir.apply(&self.usb4604).await?;
+        for pin in &mut self.pins {
+            pin.record_dir(Mode::Input, false);
+        }
+        pulls.apply(&self.usb4604).await?;
+        for pin in &mut self.pins {
+            pin.record_pull(pull);
+        }
+        Ok(())
+    }
+
+    /// Set output levels of all pins.
+    ///
+    /// Open-drain pins are released for 1 and driven low for 0, same as [Flex::set_level].
+    pub fn write(&mut self, value: u32) -> Result<(), Error> {
+        block_on(self.write_async(value))
+    }
+
+    /// Async version of [write](Self::write).
+    pub async fn write_async(&mut self, value: u32) -> Result<(), Error> {
+        let mut output = Batch::default();
+        let mut dir = Batch::default();
+        for (n, pin) in self.pins.iter().enumerate() {
+            let d = pin.descriptor();
+            let is_high = bit(value, n);
+            if pin.mode() == Mode::OutputOpenDrain {
+                if !is_high {
+                    output.set(d.registers.output, d.mask(), false);
+                }
+                dir.set(d.registers.dir, d.mask(), !is_high);
+            } else {
+                output.set(d.registers.output, d.mask(), is_high);
+            }
+        }
+        output.apply(&self.usb4604).await?;
+        dir.apply(&self.usb4604).await?;
+        for (n, pin) in self.pins.iter_mut().enumerate() {
+            let is_high = bit(value, n);
+            if pin.mode() == Mode::OutputOpenDrain {
+                pin.record_dir(Mode::OutputOpenDrain, !is_high);
+                if is_high {
+                    continue;
+                }
+            }
+            pin.record_level(Level::from(is_high));
+        }
+        Ok(())
+    }
+
+    /// Read input levels of all pins, each input register is read once.
+    pub fn read(&self) -> Result<u32, Error> {
+        block_on(self.read_async())
+    }
+
+    /// Async version of [read](Self::read).
+    pub async fn read_async(&self) -> Result<u32, Error> {
+        let mut registers: Vec<(u16, u8)> = Vec::new();
+        let mut value = 0;
+        for (n, pin) in self.pins.iter().enumerate() {
+            let d = pin.descriptor();
+            let bits = match registers
+                .iter()
+                .find(|(addr, _)| *addr == d.registers.input)
+            {
+                Some(&(_, bits)) => bits,
+                None => {
+                    let bits = self.usb4604.read_addr_async(d.registers.input).await?;
+                    registers.push((d.registers.input, bits));
+                    bits
+                }
+            };
+            if bits & d.mask() != 0 {
+                value |= 1 << n;
+            }
+        }
+        Ok(value)
+    }
+}
+
+impl Usb4604 {
+    /// Read modes of the pins from the IC and group them into a [PinGroup], first pin corresponds to bit 0.
+    pub fn pin_group(&self, pios: &[Pio]) -> Result<PinGroup, Error> {
+        block_on(self.pin_group_async(pios))
+    }
+
+    /// Async version of [pin_group](Self::pin_group).
+    pub async fn pin_group_async(&self, pios: &[Pio]) -> Result<PinGroup, Error> {
+        let mut pins = Vec::with_capacity(pios.len());
+        for &pio in pios {
+            pins.push(self.gpio_async(pio).await?);
+        }
+        PinGroup::new(pins)
+    }
+}
+
+/// Register changes collected during one call, each register is then read-modified-written once.
+#[derive(Default)]
+struct Batch {
+    /// Address, mask of bits to change and their new values, in order of first use.
+    writes: Vec<(u16, u8, u8)>,
+}
+
+impl Batch {
+    fn set(&mut self, addr: u16, mask: u8, value: bool) {
+        let bits = if value { mask } else { 0 };
+        match self.writes.iter_mut().find(|(a, _, _)| *a == addr) {
+            Some((_, m, b)) => {
+                *m |= mask;
+                *b = (*b & !mask) | bits;
+            }
+            None => self.writes.push((addr, mask, bits)),
+        }
+    }
+
+    async fn apply(&self, usb4604: &Usb4604) -> Result<(), Error> {
+        for &(addr, mask, bits) in &self.writes {
+            usb4604
+                .modify_addr_async(addr, |old| (old & !mask) | bits)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn bit(value: u32, n: usize) -> bool {
+    value & (1 << n) != 0
+}
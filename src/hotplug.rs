@@ -0,0 +1,114 @@
+use crate::blocking::poll_blocking;
+use crate::usb4604_hal::{PRODUCT_BRIDGE_DEV, VENDOR_SMSC};
+use crate::{DeviceFilter, Error, Usb4604};
+use futures_core::Stream;
+use nusb::hotplug::{HotplugEvent, HotplugWatch};
+use nusb::{DeviceId, DeviceInfo, MaybeFuture};
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Delay between attempts to open a device that has just arrived, but is not accessible yet.
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Arrival or removal of a USB4604 feature controller, see [Usb4604Watch].
+#[derive(Debug)]
+pub enum BridgeEvent {
+    Arrived(DeviceInfo),
+    Left(DeviceId),
+}
+
+/// Stream of [BridgeEvent]s, built on top of nusb hotplug events.
+///
+/// Can be used as an async [Stream] or blocking with [next_event](Self::next_event).
+pub struct Usb4604Watch {
+    watch: HotplugWatch,
+    known: HashSet<DeviceId>,
+}
+
+impl Usb4604Watch {
+    /// Start watching. Devices that are already connected are not reported as arrived,
+    /// but their removal is.
+    pub fn new() -> Result<Usb4604Watch, Error> {
+        // watch first and list after, so that no device is missed in between
+        let watch = nusb::watch_devices()?;
+        let known = nusb::list_devices()
+            .wait()?
+            .filter(is_bridge)
+            .map(|d| d.id())
+            .collect();
+        Ok(Usb4604Watch { watch, known })
+    }
+
+    /// Block until the next event, or return None if timeout expires first.
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> Option<BridgeEvent> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        poll_blocking(|cx| Pin::new(&mut *self).poll_next(cx), deadline).flatten()
+    }
+}
+
+impl Stream for Usb4604Watch {
+    type Item = BridgeEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let event = match Pin::new(&mut self.watch).poll_next(cx) {
+                Poll::Ready(Some(event)) => event,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            match event {
+                HotplugEvent::Connected(d) if is_bridge(&d) => {
+                    self.known.insert(d.id());
+                    return Poll::Ready(Some(BridgeEvent::Arrived(d)));
+                }
+                HotplugEvent::Disconnected(id) if self.known.remove(&id) => {
+                    return Poll::Ready(Some(BridgeEvent::Left(id)));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl DeviceFilter {
+    /// Wait until the selected device is connected and open it.
+    /// Returns immediately if it is already present, e.g. to get a fresh [Usb4604] after the hub was power-cycled.
+    ///
+    /// [Error::NoDevicesFound] is returned if the device did not appear before timeout expired.
+    pub fn wait_open(&self, timeout: Duration) -> Result<Usb4604, Error> {
+        let deadline = Instant::now() + timeout;
+        let mut watch = Usb4604Watch::new()?;
+        loop {
+            match self.find() {
+                Ok(info) => match info.open() {
+                    Ok(usb4604) => return Ok(usb4604),
+                    // device node may not be accessible yet right after arrival
+                    Err(e) => {
+                        if Instant::now() + OPEN_RETRY_DELAY >= deadline {
+                            return Err(e);
+                        }
+                        thread::sleep(OPEN_RETRY_DELAY);
+                        continue;
+                    }
+                },
+                Err(Error::NoDevicesFound) => {}
+                Err(e) => return Err(e),
+            }
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match watch.next_event(Some(remaining)) {
+                    Some(BridgeEvent::Arrived(_)) => break,
+                    Some(BridgeEvent::Left(_)) => {}
+                    None => return Err(Error::NoDevicesFound),
+                }
+            }
+        }
+    }
+}
+
+fn is_bridge(d: &DeviceInfo) -> bool {
+    d.vendor_id() == VENDOR_SMSC && d.product_id() == PRODUCT_BRIDGE_DEV
+}
@@ -1,32 +1,67 @@
-use crate::Error;
+use crate::blocking::{block_on, sleep};
+use crate::builder::TransferPolicy;
+use crate::{Error, Usb4604};
 use bitfield_struct::bitfield;
-use embedded_hal::i2c::{ErrorType, I2c, NoAcknowledgeSource, Operation, SevenBitAddress};
-use nusb::Interface;
-use nusb::MaybeFuture;
+use embedded_hal::i2c::{
+    ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation, SevenBitAddress,
+};
 use nusb::transfer::{ControlIn, ControlOut, ControlType, Recipient, TransferError};
 use std::fmt::{Display, Formatter};
 use std::time::Duration;
 
-const CMD_I2C_ENTER_PASSTHRU: u8 = 0x70;
-const CMD_I2C_WRITE: u8 = 0x71;
-const CMD_I2C_READ: u8 = 0x72;
+pub(crate) const CMD_I2C_ENTER_PASSTHRU: u8 = 0x70;
+pub(crate) const CMD_I2C_WRITE: u8 = 0x71;
+pub(crate) const CMD_I2C_READ: u8 = 0x72;
 
 pub struct I2cBridge {
-    interface: Interface,
-    timeout: Duration,
+    usb4604: Usb4604,
+    policy: TransferPolicy,
+    max_chunk_size: usize,
 }
 
-#[derive(Debug)]
+/// Default for [I2cBridge::set_max_chunk_size].
+pub(crate) const DEFAULT_MAX_CHUNK_SIZE: usize = 512;
+
+/// I2C transaction failure.
+///
+/// The bridge firmware reports every failure on the bus as a stalled control transfer, without telling which one,
+/// so arbitration loss and other bus errors show up as [Nack](Self::Nack) too.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum I2cError {
-    Nack,
+    /// Target did not acknowledge. Source is only known when it follows from the transfer that failed:
+    /// address for reads and zero-length writes, data for continuation chunks of a write.
+    Nack(NoAcknowledgeSource),
     WrongAddress,
+    /// Bridge returned fewer bytes than requested for a read operation, received bytes are in the buffer.
+    ShortRead {
+        requested: usize,
+        received: usize,
+    },
+    /// Transfer did not complete in time, e.g. because a target stretches the clock for too long.
+    Timeout,
     Other(TransferError),
 }
 
 impl I2cBridge {
-    pub(crate) fn init(interface: Interface) -> Result<I2cBridge, Error> {
-        let timeout = Duration::from_millis(100);
-        interface
+    /// Fails if one of the I2C pins is held as GPIO, they can't be claimed while the bridge exists.
+    pub(crate) async fn init(usb4604: Usb4604, policy: TransferPolicy) -> Result<I2cBridge, Error> {
+        usb4604.claim_i2c()?;
+        // constructed right away so that the claim is released on error
+        let i2c = I2cBridge {
+            usb4604,
+            policy,
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+        };
+        I2cBridge::enter_passthru(&i2c.usb4604, policy.timeout).await?;
+        i2c.usb4604.set_i2c_passthru();
+        Ok(i2c)
+    }
+
+    pub(crate) async fn enter_passthru(
+        usb4604: &Usb4604,
+        timeout: Duration,
+    ) -> Result<(), TransferError> {
+        usb4604
             .control_out(
                 ControlOut {
                     control_type: ControlType::Vendor,
@@ -39,17 +74,72 @@ impl I2cBridge {
                 },
                 timeout,
             )
-            .wait()?;
-        Ok(I2cBridge { interface, timeout })
+            .await
     }
-}
 
-impl I2c for I2cBridge {
-    fn transaction(
+    /// Set the largest number of bytes moved by a single USB transfer, 512 by default, clamped to 1..=65535.
+    ///
+    /// Longer reads and writes are split into several transfers that continue the same I2C transaction,
+    /// so the bus sees no extra start or stop conditions. The limit of the bridge firmware is not documented,
+    /// lower it if long transfers fail.
+    pub fn set_max_chunk_size(&mut self, size: usize) {
+        self.max_chunk_size = size.clamp(1, u16::MAX as usize);
+    }
+
+    /// Returns the largest number of bytes moved by a single USB transfer.
+    pub fn max_chunk_size(&self) -> usize {
+        self.max_chunk_size
+    }
+
+    /// Async version of [transaction](I2c::transaction).
+    pub async fn transaction_async(
         &mut self,
         address: SevenBitAddress,
         operations: &mut [Operation<'_>],
-    ) -> Result<(), Self::Error> {
+    ) -> Result<(), I2cError> {
+        // a write that timed out may still have completed on the bus, e.g. an EEPROM page write
+        let may_have_written =
+            matches!(operations.first(), Some(Operation::Write(data)) if !data.is_empty());
+        let mut retry = 0;
+        loop {
+            let mut transfers = 0;
+            match self
+                .transaction_once(address, operations, &mut transfers)
+                .await
+            {
+                Err(e)
+                    if transfers == 0
+                        && retry < self.policy.retries
+                        && self.is_retryable(e, may_have_written) =>
+                {
+                    sleep(self.policy.backoff(retry)).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Only failures before anything reached the bus are worth repeating, see [I2cBridgeBuilder](crate::I2cBridgeBuilder).
+    fn is_retryable(&self, e: I2cError, may_have_written: bool) -> bool {
+        match e {
+            I2cError::Nack(_) => self.policy.retry_on_nack,
+            I2cError::Timeout => !may_have_written,
+            I2cError::Other(e) => !matches!(
+                e,
+                TransferError::Disconnected | TransferError::InvalidArgument
+            ),
+            I2cError::WrongAddress | I2cError::ShortRead { .. } => false,
+        }
+    }
+
+    /// Run the transaction once, without retries, counting successful USB transfers.
+    pub(crate) async fn transaction_once(
+        &self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+        transfers: &mut usize,
+    ) -> Result<(), I2cError> {
         if address > 0x7F {
             return Err(I2cError::WrongAddress);
         }
@@ -60,52 +150,130 @@ impl I2c for I2cBridge {
             // generate start condition on first transaction or when read is followed by write (and vice versa, but it's never used in practice?)
             let send_start = (i == 0) || (Some(is_read) != prev_is_read);
             let is_last_transaction = i == len - 1;
-            // generate NACK when slave sent enough data
-            let send_nack = is_read && is_last_transaction;
             prev_is_read = Some(is_read);
-            let flags_addr = I2cFlagsAddress::new()
-                .with_send_start(send_start)
-                .with_send_stop(is_last_transaction)
-                .with_send_nack(send_nack)
-                .with_slave_addr(address)
-                .with_is_read(is_read);
-            match op {
-                Operation::Read(buf) => {
-                    let data = self
-                        .interface
-                        .control_in(
-                            ControlIn {
-                                control_type: ControlType::Vendor,
-                                recipient: Recipient::Interface,
-                                request: CMD_I2C_READ,
-                                value: flags_addr.into_bits(),
-                                index: 0, // reserved
-                                length: buf.len() as u16,
-                            },
-                            self.timeout,
-                        )
-                        .wait()?;
-                    buf.copy_from_slice(&data);
-                }
-                Operation::Write(buf) => {
-                    self.interface
-                        .control_out(
-                            ControlOut {
-                                control_type: ControlType::Vendor,
-                                recipient: Recipient::Interface,
-                                request: CMD_I2C_WRITE,
-                                value: flags_addr.into_bits(),
-                                index: 0, // reserved
-                                data: buf,
-                            },
-                            self.timeout,
-                        )
-                        .wait()?;
+            let op_len = match op {
+                Operation::Read(buf) => buf.len(),
+                Operation::Write(buf) => buf.len(),
+            };
+            // at least one transfer, so that zero-length operations still address the target
+            let chunks = op_len.div_ceil(self.max_chunk_size).max(1);
+            for chunk in 0..chunks {
+                let range =
+                    chunk * self.max_chunk_size..((chunk + 1) * self.max_chunk_size).min(op_len);
+                // chunks continue the same operation: start only before the first one, stop only after the last one
+                let is_last_chunk = is_last_transaction && chunk == chunks - 1;
+                // generate NACK when slave sent enough data
+                let send_nack = is_read && is_last_chunk;
+                let flags_addr = I2cFlagsAddress::new()
+                    .with_send_start(send_start && chunk == 0)
+                    .with_send_stop(is_last_chunk)
+                    .with_send_nack(send_nack)
+                    .with_slave_addr(address)
+                    .with_is_read(is_read);
+                match op {
+                    Operation::Read(buf) => {
+                        let (start, chunk_len) = (range.start, range.len());
+                        let received = self.read_chunk(flags_addr, &mut buf[range]).await?;
+                        if received < chunk_len {
+                            return Err(I2cError::ShortRead {
+                                requested: op_len,
+                                received: start + received,
+                            });
+                        }
+                    }
+                    Operation::Write(buf) => self.write_chunk(flags_addr, &buf[range]).await?,
                 }
+                *transfers += 1;
             }
         }
         Ok(())
     }
+
+    /// Returns the number of bytes received, which is less than requested on short read.
+    async fn read_chunk(
+        &self,
+        flags_addr: I2cFlagsAddress,
+        buf: &mut [u8],
+    ) -> Result<usize, I2cError> {
+        let data = self
+            .usb4604
+            .control_in_once(
+                ControlIn {
+                    control_type: ControlType::Vendor,
+                    recipient: Recipient::Interface,
+                    request: CMD_I2C_READ,
+                    value: flags_addr.into_bits(),
+                    index: 0, // reserved
+                    length: buf.len() as u16,
+                },
+                self.policy.timeout,
+            )
+            .await
+            .map_err(|e| {
+                // a target can't NACK data it sends, only its address
+                let source = if flags_addr.send_start() {
+                    NoAcknowledgeSource::Address
+                } else {
+                    NoAcknowledgeSource::Unknown
+                };
+                I2cError::from_transfer(e, source)
+            })?;
+        let received = data.len().min(buf.len());
+        buf[..received].copy_from_slice(&data[..received]);
+        Ok(received)
+    }
+
+    async fn write_chunk(&self, flags_addr: I2cFlagsAddress, data: &[u8]) -> Result<(), I2cError> {
+        self.usb4604
+            .control_out_once(
+                ControlOut {
+                    control_type: ControlType::Vendor,
+                    recipient: Recipient::Interface,
+                    request: CMD_I2C_WRITE,
+                    value: flags_addr.into_bits(),
+                    index: 0, // reserved
+                    data,
+                },
+                self.policy.timeout,
+            )
+            .await
+            .map_err(|e| {
+                let source = if !flags_addr.send_start() {
+                    NoAcknowledgeSource::Data
+                } else if data.is_empty() {
+                    NoAcknowledgeSource::Address
+                } else {
+                    NoAcknowledgeSource::Unknown
+                };
+                I2cError::from_transfer(e, source)
+            })
+    }
+}
+
+impl Drop for I2cBridge {
+    fn drop(&mut self) {
+        self.usb4604.release_i2c();
+    }
+}
+
+impl I2c for I2cBridge {
+    fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        block_on(self.transaction_async(address, operations))
+    }
+}
+
+impl embedded_hal_async::i2c::I2c for I2cBridge {
+    async fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        self.transaction_async(address, operations).await
+    }
 }
 
 impl ErrorType for I2cBridge {
@@ -113,41 +281,48 @@ impl ErrorType for I2cBridge {
 }
 
 impl embedded_hal::i2c::Error for I2cError {
-    fn kind(&self) -> embedded_hal::i2c::ErrorKind {
+    fn kind(&self) -> ErrorKind {
         match self {
-            I2cError::Nack => {
-                embedded_hal::i2c::ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown)
-            }
-            _ => embedded_hal::i2c::ErrorKind::Other,
+            I2cError::Nack(source) => ErrorKind::NoAcknowledge(*source),
+            I2cError::Timeout => ErrorKind::Bus,
+            _ => ErrorKind::Other,
         }
     }
 }
 
 #[bitfield(u16, order = Msb)]
-struct I2cFlagsAddress {
+pub(crate) struct I2cFlagsAddress {
     #[bits(5)]
     _reserved: u8,
     #[bits(1)]
-    send_nack: bool,
+    pub(crate) send_nack: bool,
     #[bits(1)]
-    send_start: bool,
+    pub(crate) send_start: bool,
     #[bits(1)]
-    send_stop: bool,
+    pub(crate) send_stop: bool,
     #[bits(7)]
-    slave_addr: u8,
+    pub(crate) slave_addr: u8,
     #[bits(1)]
-    is_read: bool,
+    pub(crate) is_read: bool,
 }
 
-impl From<TransferError> for I2cError {
-    fn from(value: TransferError) -> Self {
-        match value {
-            TransferError::Stall => I2cError::Nack,
+impl I2cError {
+    /// Stall means NACK from the source, cancelled means the transfer timed out.
+    fn from_transfer(e: TransferError, source: NoAcknowledgeSource) -> I2cError {
+        match e {
+            TransferError::Stall => I2cError::Nack(source),
+            TransferError::Cancelled => I2cError::Timeout,
             other => I2cError::Other(other),
         }
     }
 }
 
+impl From<TransferError> for I2cError {
+    fn from(e: TransferError) -> Self {
+        I2cError::from_transfer(e, NoAcknowledgeSource::Unknown)
+    }
+}
+
 impl Display for I2cError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:02X?}", self)
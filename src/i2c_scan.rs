@@ -0,0 +1,171 @@
+use crate::blocking::block_on;
+use crate::{I2cBridge, I2cError, Operation};
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+
+/// How [I2cBridge::scan] checks whether a target responds at an address.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ScanProbe {
+    /// Same as i2cdetect without options: [ReadByte](Self::ReadByte) for 0x30-0x37 and 0x50-0x5F,
+    /// where EEPROMs and write-protect registers live, [ZeroLengthWrite](Self::ZeroLengthWrite) elsewhere.
+    #[default]
+    Auto,
+    /// Address with the write bit, no data (i2cdetect -q). Some chips treat it as a command.
+    ZeroLengthWrite,
+    /// Read one byte (i2cdetect -r). Can confuse write-only chips, and a target that holds SDA low
+    /// while sending a 0 bit can't be stopped cleanly.
+    ReadByte,
+    /// SMBus quick command with the read bit: address only, no data is transferred either way.
+    SmbusQuick,
+}
+
+/// What and how to probe, see [I2cBridge::scan_with].
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    probe: ScanProbe,
+    range: RangeInclusive<u8>,
+    skip_reserved: bool,
+}
+
+/// Responding addresses found by [I2cBridge::scan], [Display] renders them as an i2cdetect grid.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ScanResult {
+    probed: Vec<u8>,
+    found: Vec<u8>,
+}
+
+/// SMBus addresses assigned to a special purpose inside 0x08-0x77: host, alert response, ACCESS.bus host,
+/// ACCESS.bus default and SMBus device default address.
+const SMBUS_RESERVED: [u8; 5] = [0x08, 0x0C, 0x28, 0x37, 0x61];
+
+impl ScanOptions {
+    /// [ScanProbe::Auto] over 0x08-0x77, same as i2cdetect.
+    pub fn new() -> Self {
+        ScanOptions {
+            probe: ScanProbe::Auto,
+            range: 0x08..=0x77,
+            skip_reserved: false,
+        }
+    }
+
+    /// How each address is probed, [ScanProbe::Auto] by default.
+    ///
+    /// [ZeroLengthWrite](ScanProbe::ZeroLengthWrite) sends start, address with the write bit and stop,
+    /// [ReadByte](ScanProbe::ReadByte) reads one byte and NACKs it, [SmbusQuick](ScanProbe::SmbusQuick) sends
+    /// the address with the read bit and stops without reading. [Auto](ScanProbe::Auto) reads a byte from
+    /// 0x30-0x37 and 0x50-0x5F and does a zero-length write everywhere else.
+    pub fn probe(mut self, probe: ScanProbe) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    /// Addresses to probe, clamped to 0x00-0x7F.
+    pub fn range(mut self, range: RangeInclusive<u8>) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Skip I2C reserved addresses (0x00-0x07, 0x78-0x7F) and SMBus special purpose ones, disabled by default.
+    pub fn skip_reserved(mut self, skip: bool) -> Self {
+        self.skip_reserved = skip;
+        self
+    }
+
+    fn addresses(&self) -> impl Iterator<Item = u8> + '_ {
+        self.range.clone().filter(move |&address| {
+            address <= 0x7F
+                && !(self.skip_reserved
+                    && (!(0x08..=0x77).contains(&address) || SMBUS_RESERVED.contains(&address)))
+        })
+    }
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions::new()
+    }
+}
+
+impl ScanResult {
+    /// Addresses that acknowledged the probe, in ascending order.
+    pub fn addresses(&self) -> &[u8] {
+        &self.found
+    }
+
+    /// Returns true if the address was probed, as opposed to skipped.
+    pub fn is_probed(&self, address: u8) -> bool {
+        self.probed.contains(&address)
+    }
+}
+
+impl Display for ScanResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "     0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f")?;
+        for row in (0..0x80u8).step_by(16) {
+            write!(f, "{row:02x}: ")?;
+            for address in row..row + 16 {
+                if self.found.contains(&address) {
+                    write!(f, "{address:02x} ")?;
+                } else if self.is_probed(address) {
+                    write!(f, "-- ")?;
+                } else {
+                    write!(f, "   ")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl I2cBridge {
+    /// Probe 0x08-0x77 the way i2cdetect does, see [ScanOptions::new].
+    pub fn scan(&mut self) -> Result<ScanResult, I2cError> {
+        block_on(self.scan_async())
+    }
+
+    /// Async version of [scan](Self::scan).
+    pub async fn scan_async(&mut self) -> Result<ScanResult, I2cError> {
+        self.scan_with_async(&ScanOptions::new()).await
+    }
+
+    /// Probe every address in options one by one, an address responds if it does not NACK.
+    ///
+    /// Probing writes to or reads from every target on the bus, which is not harmless for all chips,
+    /// see [ScanProbe]. Any error other than NACK aborts the scan. Every address is probed once,
+    /// the retry settings of the bridge are not applied.
+    pub fn scan_with(&mut self, options: &ScanOptions) -> Result<ScanResult, I2cError> {
+        block_on(self.scan_with_async(options))
+    }
+
+    /// Async version of [scan_with](Self::scan_with).
+    pub async fn scan_with_async(&mut self, options: &ScanOptions) -> Result<ScanResult, I2cError> {
+        let mut result = ScanResult {
+            probed: Vec::new(),
+            found: Vec::new(),
+        };
+        for address in options.addresses() {
+            let probe = match options.probe {
+                ScanProbe::Auto if matches!(address, 0x30..=0x37 | 0x50..=0x5F) => {
+                    ScanProbe::ReadByte
+                }
+                ScanProbe::Auto => ScanProbe::ZeroLengthWrite,
+                probe => probe,
+            };
+            let mut byte = [0u8];
+            let mut operation = match probe {
+                ScanProbe::ReadByte => Operation::Read(&mut byte),
+                ScanProbe::SmbusQuick => Operation::Read(&mut []),
+                _ => Operation::Write(&[]),
+            };
+            result.probed.push(address);
+            let operations = std::slice::from_mut(&mut operation);
+            match self.transaction_once(address, operations, &mut 0).await {
+                Ok(()) => result.found.push(address),
+                Err(I2cError::Nack(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(result)
+    }
+}
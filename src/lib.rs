@@ -1,15 +1,52 @@
+mod blocking;
+mod builder;
+mod chip;
+mod enumeration;
 mod gpio;
+mod group;
+mod hotplug;
 mod i2c;
+mod i2c_scan;
+pub mod pin_map;
+mod probe;
+mod safe_state;
+pub mod sim;
+mod transport;
 mod usb4604_hal;
 pub mod usb4604_reg;
+mod wait;
 
+pub use builder::{I2cBridgeBuilder, Usb4604Builder};
+pub use chip::ChipVariant;
 pub use embedded_hal::i2c::{I2c, Operation};
-pub use gpio::{Flex, Input, Level, Mode, OpenDrainOutput, Pio, PioIter, Pull, PushPullOutput};
+pub use enumeration::{DeviceFilter, DownstreamPort, HubTopology, Usb4604Info};
+pub use gpio::{
+    DropPolicy, ExtendedPio, ExtendedPioIter, Flex, Input, Level, Mode, OpenDrainOutput, Pins, Pio,
+    PioIter, Pull, PushPullOutput, SideEffect,
+};
+pub use group::PinGroup;
+pub use hotplug::{BridgeEvent, Usb4604Watch};
 pub use i2c::{I2cBridge, I2cError};
+pub use i2c_scan::{ScanOptions, ScanProbe, ScanResult};
 use nusb::transfer::TransferError;
+pub use probe::PinCapabilities;
+pub use safe_state::{SafeState, SafeStateGuard};
 use std::fmt::{Display, Formatter};
+pub use transport::{TransferFuture, Transport};
 pub use usb4604_hal::Usb4604;
 
+// Handles are meant to be moved between threads, keep it that way.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Usb4604>();
+    assert_send_sync::<Flex>();
+    assert_send_sync::<Input>();
+    assert_send_sync::<PushPullOutput>();
+    assert_send_sync::<OpenDrainOutput>();
+    assert_send_sync::<PinGroup>();
+    assert_send_sync::<I2cBridge>();
+};
+
 pub trait SmscReg {
     const ADDR: u16;
     fn from_value(bits: u8) -> Self;
@@ -22,6 +59,14 @@ pub enum Error {
     Nusb(nusb::Error),
     NoDevicesFound,
     MultipleDevicesFound,
+    /// Pin was requested without acknowledging one of its side effects, see [ExtendedPio::side_effects].
+    UnacknowledgedSideEffect(SideEffect),
+    /// Pin does not exist on the detected chip, see [Usb4604::chip_variant].
+    PinNotAvailable(ExtendedPio),
+    /// Pin is already held by another live handle, drop it first.
+    PinInUse(ExtendedPio),
+    /// Pin is shared with the I2C master, it can't be used as GPIO while an [I2cBridge] exists and vice versa.
+    PinUsedByI2c(ExtendedPio),
     Other(&'static str),
 }
 
@@ -33,6 +78,12 @@ impl Display for Error {
 
 impl std::error::Error for Error {}
 
+impl embedded_hal::digital::Error for Error {
+    fn kind(&self) -> embedded_hal::digital::ErrorKind {
+        embedded_hal::digital::ErrorKind::Other
+    }
+}
+
 impl From<TransferError> for Error {
     fn from(e: TransferError) -> Error {
         Error::TransferError(e)
@@ -0,0 +1,154 @@
+//! Where each GPIO lives in the register map.
+//!
+//! [Flex](crate::Flex) and everything built on it only ever access pins through a [PinDescriptor],
+//! so supporting a new pin is a matter of adding an entry to the table.
+
+use crate::usb4604_reg::*;
+use crate::{ExtendedPio, SideEffect, SmscReg};
+use SideEffect::*;
+
+/// Addresses of the registers that control a group of up to 8 pins, one bit per pin.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PinRegisters {
+    pub dir: u16,
+    pub output: u16,
+    pub input: u16,
+    pub pull_up: u16,
+    pub pull_down: u16,
+}
+
+/// Registers and bit position of a GPIO, plus what else changes when it is used.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PinDescriptor {
+    pub pio: ExtendedPio,
+    /// GPIO number as in the datasheet.
+    pub number: u8,
+    pub registers: PinRegisters,
+    /// Bit position of the pin in each of the registers.
+    pub bit: u8,
+    /// Documented consequences of configuring this pin, see [SideEffect].
+    pub side_effects: &'static [SideEffect],
+}
+
+impl PinDescriptor {
+    pub(crate) fn mask(&self) -> u8 {
+        1 << self.bit
+    }
+
+    /// Returns the first side effect that is not in `acknowledged`.
+    ///
+    /// Side effects of [verified](crate::Pio) pins are informational, choosing such a pin acknowledges them,
+    /// so that all ways of using a pin agree with [Usb4604::gpio](crate::Usb4604::gpio).
+    pub(crate) fn unacknowledged(&self, acknowledged: &[SideEffect]) -> Option<SideEffect> {
+        if self.pio.verified().is_some() {
+            return None;
+        }
+        self.side_effects
+            .iter()
+            .find(|effect| !acknowledged.contains(effect))
+            .copied()
+    }
+}
+
+pub const GPIO0_7: PinRegisters = PinRegisters {
+    dir: Gpio0_7Dir::ADDR,
+    output: Gpio0_7Output::ADDR,
+    input: Gpio0_7Input::ADDR,
+    pull_up: Gpio0_7PullUp::ADDR,
+    pull_down: Gpio0_7PullDown::ADDR,
+};
+
+pub const GPIO8_10: PinRegisters = PinRegisters {
+    dir: Gpio8_10Dir::ADDR,
+    output: Gpio8_10Output::ADDR,
+    input: Gpio8_10Input::ADDR,
+    pull_up: Gpio8_10PullUp::ADDR,
+    pull_down: Gpio8_10PullDown::ADDR,
+};
+
+pub const GPIO17_20: PinRegisters = PinRegisters {
+    dir: Gpio17_20Dir::ADDR,
+    output: Gpio17_20Output::ADDR,
+    input: Gpio17_20Input::ADDR,
+    pull_up: Gpio17_20PullUp::ADDR,
+    pull_down: Gpio17_20PullDown::ADDR,
+};
+
+pub const GPIO41_45: PinRegisters = PinRegisters {
+    dir: Gpio41_45Dir::ADDR,
+    output: Gpio41_45Output::ADDR,
+    input: Gpio41_45Input::ADDR,
+    pull_up: Gpio41_45PullUp::ADDR,
+    pull_down: Gpio41_45PullDown::ADDR,
+};
+
+/// Every GPIO register bank.
+pub(crate) const BANKS: &[PinRegisters] = &[GPIO0_7, GPIO8_10, GPIO17_20, GPIO41_45];
+
+impl PinRegisters {
+    /// Registers that only change when written over USB, i.e. all except input.
+    pub(crate) fn configuration(&self) -> [u16; 4] {
+        [self.dir, self.output, self.pull_up, self.pull_down]
+    }
+}
+
+const fn pin(
+    pio: ExtendedPio,
+    number: u8,
+    registers: PinRegisters,
+    bit: u8,
+    side_effects: &'static [SideEffect],
+) -> PinDescriptor {
+    PinDescriptor {
+        pio,
+        number,
+        registers,
+        bit,
+        side_effects,
+    }
+}
+
+/// All GPIOs of USB4604 that have bits in the register map.
+///
+/// Other variants have a subset of them, see [ChipVariant::pins](crate::ChipVariant::pins).
+#[rustfmt::skip]
+pub const USB4604_PINS: &[PinDescriptor] = &[
+    pin(ExtendedPio::Pio0, 0, GPIO0_7, 0, &[]),
+    pin(ExtendedPio::Pio1, 1, GPIO0_7, 1, &[]),
+    pin(ExtendedPio::Pio2, 2, GPIO0_7, 2, &[DisablesI2c, Unverified]),
+    pin(ExtendedPio::Pio3, 3, GPIO0_7, 3, &[]),
+    pin(ExtendedPio::Pio5, 5, GPIO0_7, 5, &[Unverified]),
+    pin(ExtendedPio::Pio8, 8, GPIO8_10, 0, &[]),
+    pin(ExtendedPio::Pio9, 9, GPIO8_10, 1, &[]),
+    pin(ExtendedPio::Pio10, 10, GPIO8_10, 2, &[]),
+    pin(ExtendedPio::Pio17, 17, GPIO17_20, 1, &[DisablesOverCurrentSense { port: 1 }, Unverified]),
+    pin(ExtendedPio::Pio18, 18, GPIO17_20, 2, &[DisablesOverCurrentSense { port: 2 }, Unverified]),
+    pin(ExtendedPio::Pio19, 19, GPIO17_20, 3, &[DisablesOverCurrentSense { port: 3 }]),
+    pin(ExtendedPio::Pio20, 20, GPIO17_20, 4, &[DisablesOverCurrentSense { port: 4 }]),
+    pin(ExtendedPio::Pio41, 41, GPIO41_45, 1, &[DisablesPortPower { port: 1 }, Unverified]),
+    pin(ExtendedPio::Pio42, 42, GPIO41_45, 2, &[DisablesPortPower { port: 2 }, Unverified]),
+    pin(ExtendedPio::Pio43, 43, GPIO41_45, 3, &[DisablesPortPower { port: 3 }, Unverified]),
+    pin(ExtendedPio::Pio44, 44, GPIO41_45, 4, &[DisablesPortPower { port: 4 }, Unverified]),
+    pin(ExtendedPio::Pio45, 45, GPIO41_45, 5, &[DisablesI2c, Unverified]),
+];
+
+impl ExtendedPio {
+    /// Entry of this pin in [USB4604_PINS], see [ChipVariant::pin](crate::ChipVariant::pin) for other chips.
+    pub fn descriptor(self) -> &'static PinDescriptor {
+        USB4604_PINS
+            .iter()
+            .find(|pin| pin.pio == self)
+            .expect("every ExtendedPio is in the pin table")
+    }
+
+    /// GPIO number as in the datasheet.
+    pub fn number(self) -> u8 {
+        self.descriptor().number
+    }
+
+    /// Documented consequences of configuring this pin,
+    /// that must be acknowledged in [Usb4604::gpio_extended](crate::Usb4604::gpio_extended) unless it is in [Pio](crate::Pio).
+    pub fn side_effects(self) -> &'static [SideEffect] {
+        self.descriptor().side_effects
+    }
+}
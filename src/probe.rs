@@ -0,0 +1,123 @@
+use crate::blocking::block_on;
+use crate::pin_map::PinDescriptor;
+use crate::{Error, ExtendedPio, SideEffect, Usb4604};
+use nusb::transfer::TransferError;
+
+/// What could be controlled on a pin during [Usb4604::probe_pins].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PinCapabilities {
+    pub pio: ExtendedPio,
+    /// Direction bit can be both set and cleared.
+    pub direction: bool,
+    /// Output bit can be both set and cleared.
+    pub output: bool,
+    pub pull_up: bool,
+    pub pull_down: bool,
+    /// Input register reads back both levels driven by the pin itself,
+    /// only checked if direction and output are settable.
+    pub input_follows_output: bool,
+}
+
+impl PinCapabilities {
+    /// Returns true if direction, output level and pulls can all be controlled.
+    ///
+    /// Input is not taken into account, on some pins input buffer seems to be disabled while they are outputs.
+    pub fn is_working(&self) -> bool {
+        self.direction && self.output && self.pull_up && self.pull_down
+    }
+}
+
+impl Usb4604 {
+    /// Find out which GPIOs of the [chip variant](Self::chip_variant) are actually controllable from the USB interface on this particular IC and board.
+    ///
+    /// Every Dir, Output, PullUp and PullDown bit is written to both values and read back, then the original
+    /// register value is restored. If a pin accepts being an output, it is driven low and high for a moment to check
+    /// that input register follows it, so disconnect anything that might not like that.
+    ///
+    /// Only pins whose [side effects](ExtendedPio::side_effects) are all in `acknowledged` are probed,
+    /// pass an empty slice to only probe pins without any and [verified](crate::Pio) ones,
+    /// same as [gpio_extended](Self::gpio_extended) would open.
+    /// Pin handles that are alive during probing are not aware of it, but their configuration is restored.
+    /// Registers are always read from the device, bypassing the [shadow cache](Self::set_shadow_cache).
+    pub fn probe_pins(&self, acknowledged: &[SideEffect]) -> Result<Vec<PinCapabilities>, Error> {
+        block_on(self.probe_pins_async(acknowledged))
+    }
+
+    /// Async version of [probe_pins](Self::probe_pins).
+    pub async fn probe_pins_async(
+        &self,
+        acknowledged: &[SideEffect],
+    ) -> Result<Vec<PinCapabilities>, Error> {
+        let mut report = Vec::new();
+        for pin in self.chip_variant().pins() {
+            if pin.unacknowledged(acknowledged).is_none() {
+                report.push(self.probe_pin(pin).await?);
+            }
+        }
+        Ok(report)
+    }
+
+    async fn probe_pin(&self, pin: &PinDescriptor) -> Result<PinCapabilities, Error> {
+        // pin handles used from other threads must not modify the bank while it is being restored
+        let _guard = self.lock_registers().await;
+        let regs = pin.registers;
+        let mask = pin.mask();
+        let direction = self.probe_bit(regs.dir, mask).await?;
+        let output = self.probe_bit(regs.output, mask).await?;
+        let pull_up = self.probe_bit(regs.pull_up, mask).await?;
+        let pull_down = self.probe_bit(regs.pull_down, mask).await?;
+        let input_follows_output = if direction && output {
+            let dir = self.read_addr_uncached_async(regs.dir).await?;
+            let out = self.read_addr_uncached_async(regs.output).await?;
+            let follows = async {
+                let mut follows = true;
+                self.write_addr_async(regs.dir, dir | mask).await?;
+                for is_high in [false, true] {
+                    let level = if is_high { out | mask } else { out & !mask };
+                    self.write_addr_async(regs.output, level).await?;
+                    let input = self.read_addr_uncached_async(regs.input).await?;
+                    follows &= (input & mask != 0) == is_high;
+                }
+                Ok::<_, TransferError>(follows)
+            }
+            .await;
+            // restored even if the check failed halfway, so that the pin is not left driven
+            // output first, so that a pin that was an output returns straight to its original level
+            let restored_output = self.write_addr_async(regs.output, out).await;
+            let restored_dir = self.write_addr_async(regs.dir, dir).await;
+            let follows = follows?;
+            restored_output?;
+            restored_dir?;
+            follows
+        } else {
+            false
+        };
+        Ok(PinCapabilities {
+            pio: pin.pio,
+            direction,
+            output,
+            pull_up,
+            pull_down,
+            input_follows_output,
+        })
+    }
+
+    /// Returns true if the masked bit reads back both set and cleared, register is restored afterwards.
+    async fn probe_bit(&self, addr: u16, mask: u8) -> Result<bool, Error> {
+        let original = self.read_addr_uncached_async(addr).await?;
+        let settable = async {
+            let mut settable = true;
+            for value in [original | mask, original & !mask] {
+                self.write_addr_async(addr, value).await?;
+                settable &= self.read_addr_uncached_async(addr).await? & mask == value & mask;
+            }
+            Ok::<_, TransferError>(settable)
+        }
+        .await;
+        // restored even if a transfer failed halfway, the first error is returned
+        let restored = self.write_addr_async(addr, original).await;
+        let settable = settable?;
+        restored?;
+        Ok(settable)
+    }
+}
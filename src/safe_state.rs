@@ -0,0 +1,173 @@
+use crate::blocking::block_on;
+use crate::gpio::PinState;
+use crate::pin_map::PinDescriptor;
+use crate::{Error, ExtendedPio, Level, Pull, SideEffect, Usb4604};
+#[cfg(feature = "signal")]
+use std::sync::Mutex;
+#[cfg(feature = "signal")]
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+
+/// Pin configuration to fall back to when the program stops controlling the hub, see [Usb4604::set_safe_state].
+///
+/// Typically keeps power enable and reset lines of the device under test in a known state.
+///
+/// Covers the program exiting, panicking or being interrupted by a signal, not the hub going away: a disconnected
+/// device can't be written to, and if it comes back with [reconnect](Usb4604::set_reconnect) enabled,
+/// the configuration of live pin handles is replayed instead.
+#[derive(Clone, Default, Debug)]
+pub struct SafeState {
+    pins: Vec<(ExtendedPio, SafePin)>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SafePin {
+    Output(Level),
+    Input(Pull),
+}
+
+/// Applies the safe state of a device when dropped, see [Usb4604::safe_state_guard].
+pub struct SafeStateGuard {
+    usb4604: Usb4604,
+}
+
+/// Devices to put into safe state on SIGINT or SIGTERM, see [Usb4604::apply_safe_state_on_signal].
+#[cfg(feature = "signal")]
+static SIGNAL_DEVICES: Mutex<Vec<Usb4604>> = Mutex::new(Vec::new());
+#[cfg(feature = "signal")]
+static SIGNAL_HANDLER_INSTALLED: Mutex<bool> = Mutex::new(false);
+#[cfg(feature = "signal")]
+static SIGNAL_EXIT_CODE: AtomicI32 = AtomicI32::new(130);
+
+impl SafePin {
+    fn state(self, pin: &'static PinDescriptor) -> PinState {
+        match self {
+            SafePin::Output(level) => PinState::output(pin, level),
+            SafePin::Input(pull) => PinState::input(pin, pull),
+        }
+    }
+}
+
+impl SafeState {
+    pub fn new() -> SafeState {
+        SafeState::default()
+    }
+
+    /// Drive the pin to a level in push-pull mode, pulls are disabled.
+    pub fn output(mut self, pio: impl Into<ExtendedPio>, level: Level) -> SafeState {
+        self.set(pio.into(), SafePin::Output(level));
+        self
+    }
+
+    /// Put the pin into input mode with the given pull-up or pull-down configuration.
+    pub fn input(mut self, pio: impl Into<ExtendedPio>, pull: Pull) -> SafeState {
+        self.set(pio.into(), SafePin::Input(pull));
+        self
+    }
+
+    fn set(&mut self, pio: ExtendedPio, config: SafePin) {
+        self.pins.retain(|(p, _)| *p != pio);
+        self.pins.push((pio, config));
+    }
+}
+
+impl Usb4604 {
+    /// Register (or remove with None) the safe state profile of this device, shared by all clones.
+    ///
+    /// It is applied by [apply_safe_state](Self::apply_safe_state), when a [guard](Self::safe_state_guard)
+    /// is dropped, e.g. during a panic, and on SIGINT or SIGTERM if [enabled](Self::apply_safe_state_on_signal).
+    /// Every pin must exist on the [chip variant](Self::chip_variant) and all of its
+    /// [side effects](ExtendedPio::side_effects) must be in `acknowledged`, as in [gpio_extended](Self::gpio_extended).
+    pub fn set_safe_state(
+        &self,
+        state: Option<SafeState>,
+        acknowledged: &[SideEffect],
+    ) -> Result<(), Error> {
+        for (pio, _) in state.iter().flat_map(|state| &state.pins) {
+            if let Some(effect) = self.pin(*pio)?.unacknowledged(acknowledged) {
+                return Err(Error::UnacknowledgedSideEffect(effect));
+            }
+        }
+        *self.safe_state().lock().unwrap() = state;
+        Ok(())
+    }
+
+    /// Put the pins of the registered safe state profile into their safe configuration, e.g. before exiting.
+    ///
+    /// Pin handles are bypassed and not aware of it, pins stay claimed. Does nothing if no profile is set.
+    /// All pins are attempted even if some of them fail, the first error is returned.
+    pub fn apply_safe_state(&self) -> Result<(), Error> {
+        block_on(self.apply_safe_state_async())
+    }
+
+    /// Async version of [apply_safe_state](Self::apply_safe_state).
+    pub async fn apply_safe_state_async(&self) -> Result<(), Error> {
+        let Some(state) = self.safe_state().lock().unwrap().clone() else {
+            return Ok(());
+        };
+        let mut usb4604 = self.clone();
+        let mut result = Ok(());
+        for (pio, config) in state.pins {
+            let applied = match self.pin(pio) {
+                Ok(pin) => config.state(pin).replay(&mut usb4604).await,
+                Err(e) => Err(e),
+            };
+            result = result.and(applied);
+        }
+        result
+    }
+
+    /// Returns a guard that [applies the safe state](Self::apply_safe_state) when dropped,
+    /// whether it goes out of scope normally or while unwinding from a panic.
+    ///
+    /// Like a pin [drop policy](crate::DropPolicy), it is applied on a helper thread if a read-modify-write
+    /// is in progress at that moment, so that a task paused on the same thread can't deadlock it.
+    pub fn safe_state_guard(&self) -> SafeStateGuard {
+        SafeStateGuard {
+            usb4604: self.clone(),
+        }
+    }
+
+    /// Apply the safe state of this device when the process receives SIGINT, SIGTERM or SIGHUP,
+    /// then exit with `exit_code`.
+    ///
+    /// The handler can't tell the signals apart, so the same code is used for all of them, e.g. 130 as for
+    /// an interrupted program, the one from the latest call wins. Installs a process-wide handler on first call,
+    /// fails if the program (or another crate) already installed one with [ctrlc](https://docs.rs/ctrlc).
+    /// The device stays open until the process exits. Requires the `signal` feature.
+    #[cfg(feature = "signal")]
+    pub fn apply_safe_state_on_signal(&self, exit_code: i32) -> Result<(), Error> {
+        SIGNAL_EXIT_CODE.store(exit_code, Ordering::Relaxed);
+        let mut installed = SIGNAL_HANDLER_INSTALLED.lock().unwrap();
+        if !*installed {
+            ctrlc::set_handler(|| {
+                for usb4604 in SIGNAL_DEVICES.lock().unwrap().iter() {
+                    let _ = usb4604.apply_safe_state();
+                }
+                std::process::exit(SIGNAL_EXIT_CODE.load(Ordering::Relaxed));
+            })
+            .map_err(|_| Error::Other("signal handler is already installed"))?;
+            *installed = true;
+        }
+        let mut devices = SIGNAL_DEVICES.lock().unwrap();
+        if !devices.iter().any(|device| device.is_same_device(self)) {
+            devices.push(self.clone());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SafeStateGuard {
+    fn drop(&mut self) {
+        // nowhere to report an error to
+        if self.usb4604.is_register_lock_held() {
+            // the holder might be a task paused on this very thread, see DropPolicy
+            let usb4604 = self.usb4604.clone();
+            thread::spawn(move || {
+                let _ = usb4604.apply_safe_state();
+            });
+        } else {
+            let _ = self.usb4604.apply_safe_state();
+        }
+    }
+}
@@ -0,0 +1,434 @@
+//! In-memory model of the USB4604 feature controller.
+//!
+//! [SimHub] implements [Transport], so it can be passed to [Usb4604::new](crate::Usb4604::new) and used to
+//! exercise GPIO and I2C code without a physical hub:
+//!
+//! ```
+//! use usb4604::sim::SimHub;
+//! use usb4604::{Level, Pio, Usb4604};
+//!
+//! let sim = SimHub::new();
+//! let usb4604 = Usb4604::new(sim.clone());
+//! let mut pio0 = usb4604.output(Pio::Pio0, Some(Level::High)).unwrap();
+//! assert_eq!(sim.output_level(0), Some(Level::High));
+//! pio0.set_low().unwrap();
+//! assert_eq!(sim.output_level(0), Some(Level::Low));
+//! ```
+
+use crate::i2c::{CMD_I2C_ENTER_PASSTHRU, CMD_I2C_READ, CMD_I2C_WRITE, I2cFlagsAddress};
+use crate::usb4604_hal::{CMD_REG_READ, CMD_REG_WRITE};
+use crate::usb4604_reg::*;
+use crate::{Level, SmscReg, Transport};
+use nusb::transfer::{ControlIn, ControlOut, ControlType, Recipient, TransferError};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// I2C device attached to the simulated hub's I2C master.
+///
+/// Returning false from any of the methods NACKs the transfer, which the bridge reports as a control transfer stall.
+pub trait I2cTarget: Send {
+    /// Start (or repeated start) condition addressed to this target.
+    fn start(&mut self, is_read: bool) -> bool {
+        let _ = is_read;
+        true
+    }
+
+    /// Bytes written by the master.
+    fn write(&mut self, data: &[u8]) -> bool;
+
+    /// Fill buf with bytes requested by the master.
+    fn read(&mut self, buf: &mut [u8]) -> bool;
+
+    /// Stop condition.
+    fn stop(&mut self) {}
+}
+
+/// Simulated feature controller, cheap to clone, all clones share the same state.
+#[derive(Clone, Default)]
+pub struct SimHub {
+    state: Arc<Mutex<SimState>>,
+}
+
+#[derive(Default)]
+struct SimState {
+    banks: [BankState; 4],
+    /// Level driven onto a pin from outside, None if floating.
+    external: BTreeMap<u8, Level>,
+    i2c_passthru: bool,
+    i2c_active: Option<u8>,
+    i2c_targets: BTreeMap<u8, Box<dyn I2cTarget>>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct BankState {
+    dir: u8,
+    output: u8,
+    pull_up: u8,
+    pull_down: u8,
+}
+
+struct Bank {
+    dir: u16,
+    output: u16,
+    input: u16,
+    pull_up: u16,
+    pull_down: u16,
+    /// GPIO number corresponding to bit 0.
+    offset: u8,
+    /// Implemented bits.
+    mask: u8,
+}
+
+const BANKS: [Bank; 4] = [
+    Bank {
+        dir: Gpio0_7Dir::ADDR,
+        output: Gpio0_7Output::ADDR,
+        input: Gpio0_7Input::ADDR,
+        pull_up: Gpio0_7PullUp::ADDR,
+        pull_down: Gpio0_7PullDown::ADDR,
+        offset: 0,
+        mask: 0xFF,
+    },
+    Bank {
+        dir: Gpio8_10Dir::ADDR,
+        output: Gpio8_10Output::ADDR,
+        input: Gpio8_10Input::ADDR,
+        pull_up: Gpio8_10PullUp::ADDR,
+        pull_down: Gpio8_10PullDown::ADDR,
+        offset: 8,
+        mask: 0x07,
+    },
+    Bank {
+        dir: Gpio17_20Dir::ADDR,
+        output: Gpio17_20Output::ADDR,
+        input: Gpio17_20Input::ADDR,
+        pull_up: Gpio17_20PullUp::ADDR,
+        pull_down: Gpio17_20PullDown::ADDR,
+        offset: 16,
+        mask: 0x1E,
+    },
+    Bank {
+        dir: Gpio41_45Dir::ADDR,
+        output: Gpio41_45Output::ADDR,
+        input: Gpio41_45Input::ADDR,
+        pull_up: Gpio41_45PullUp::ADDR,
+        pull_down: Gpio41_45PullDown::ADDR,
+        offset: 40,
+        mask: 0x3E,
+    },
+];
+
+#[derive(Clone, Copy)]
+enum RegKind {
+    Dir,
+    Output,
+    Input,
+    PullUp,
+    PullDown,
+}
+
+fn lookup(addr: u16) -> Option<(usize, RegKind)> {
+    BANKS.iter().enumerate().find_map(|(i, b)| {
+        let kind = match addr {
+            a if a == b.dir => RegKind::Dir,
+            a if a == b.output => RegKind::Output,
+            a if a == b.input => RegKind::Input,
+            a if a == b.pull_up => RegKind::PullUp,
+            a if a == b.pull_down => RegKind::PullDown,
+            _ => return None,
+        };
+        Some((i, kind))
+    })
+}
+
+fn gpio_bit(gpio: u8) -> Option<(usize, u8)> {
+    BANKS.iter().enumerate().find_map(|(i, b)| {
+        let bit = gpio.checked_sub(b.offset)?;
+        (bit < 8 && b.mask & (1 << bit) != 0).then(|| (i, 1 << bit))
+    })
+}
+
+impl SimHub {
+    /// Create a simulated hub with all GPIOs in input mode and no pulls, as after reset.
+    pub fn new() -> SimHub {
+        SimHub::default()
+    }
+
+    /// Attach an I2C target at a 7-bit address, replacing any previous one.
+    pub fn add_i2c_target(&self, address: u8, target: impl I2cTarget + 'static) {
+        self.state().i2c_targets.insert(address, Box::new(target));
+    }
+
+    /// Remove the I2C target at address.
+    pub fn remove_i2c_target(&self, address: u8) {
+        self.state().i2c_targets.remove(&address);
+    }
+
+    /// Drive a GPIO from outside with the given level, or let it float with None.
+    pub fn drive(&self, gpio: u8, level: Option<Level>) {
+        let mut state = self.state();
+        match level {
+            Some(level) => state.external.insert(gpio, level),
+            None => state.external.remove(&gpio),
+        };
+    }
+
+    /// Returns the level driven by the hub onto a GPIO, or None if it is not configured as output.
+    pub fn output_level(&self, gpio: u8) -> Option<Level> {
+        let (bank, bit) = gpio_bit(gpio)?;
+        let regs = self.state().banks[bank];
+        (regs.dir & bit != 0).then(|| Level::from(regs.output & bit != 0))
+    }
+
+    /// Returns the level seen by the GPIO input buffer.
+    pub fn input_level(&self, gpio: u8) -> Option<Level> {
+        let (bank, bit) = gpio_bit(gpio)?;
+        let input = self.state().input(bank);
+        Some(Level::from(input & bit != 0))
+    }
+
+    /// Returns the current value of a register, or None if the address is not modeled.
+    pub fn register(&self, addr: u16) -> Option<u8> {
+        let (bank, kind) = lookup(addr)?;
+        Some(self.state().read(bank, kind))
+    }
+
+    /// Returns true if I2C pass-through mode was entered.
+    pub fn is_i2c_passthru(&self) -> bool {
+        self.state().i2c_passthru
+    }
+
+    fn state(&self) -> MutexGuard<'_, SimState> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl SimState {
+    fn input(&self, bank: usize) -> u8 {
+        let b = &BANKS[bank];
+        let regs = &self.banks[bank];
+        let mut input = 0;
+        for bit in (0..8).filter(|bit| b.mask & (1 << bit) != 0) {
+            let mask = 1 << bit;
+            let high = if regs.dir & mask != 0 {
+                regs.output & mask != 0
+            } else if let Some(level) = self.external.get(&(b.offset + bit)) {
+                *level == Level::High
+            } else {
+                regs.pull_up & mask != 0
+            };
+            if high {
+                input |= mask;
+            }
+        }
+        input
+    }
+
+    fn read(&self, bank: usize, kind: RegKind) -> u8 {
+        let regs = &self.banks[bank];
+        match kind {
+            RegKind::Dir => regs.dir,
+            RegKind::Output => regs.output,
+            RegKind::Input => self.input(bank),
+            RegKind::PullUp => regs.pull_up,
+            RegKind::PullDown => regs.pull_down,
+        }
+    }
+
+    fn write(&mut self, bank: usize, kind: RegKind, value: u8) -> Result<(), TransferError> {
+        let value = value & BANKS[bank].mask;
+        let regs = &mut self.banks[bank];
+        match kind {
+            RegKind::Dir => regs.dir = value,
+            RegKind::Output => regs.output = value,
+            RegKind::Input => return Err(TransferError::Stall),
+            RegKind::PullUp => regs.pull_up = value,
+            RegKind::PullDown => regs.pull_down = value,
+        }
+        Ok(())
+    }
+
+    fn i2c_begin(
+        &mut self,
+        flags: I2cFlagsAddress,
+    ) -> Result<&mut Box<dyn I2cTarget>, TransferError> {
+        if !self.i2c_passthru {
+            return Err(TransferError::Stall);
+        }
+        if flags.send_start() {
+            let address = flags.slave_addr();
+            let target = self
+                .i2c_targets
+                .get_mut(&address)
+                .ok_or(TransferError::Stall)?;
+            if !target.start(flags.is_read()) {
+                return Err(TransferError::Stall);
+            }
+            self.i2c_active = Some(address);
+        }
+        let address = self.i2c_active.ok_or(TransferError::Stall)?;
+        self.i2c_targets
+            .get_mut(&address)
+            .ok_or(TransferError::Stall)
+    }
+
+    fn i2c_end(&mut self, flags: I2cFlagsAddress, ok: bool) -> Result<(), TransferError> {
+        if (flags.send_stop() || !ok)
+            && let Some(target) = self
+                .i2c_active
+                .take()
+                .and_then(|a| self.i2c_targets.get_mut(&a))
+        {
+            target.stop();
+        }
+        if ok {
+            Ok(())
+        } else {
+            Err(TransferError::Stall)
+        }
+    }
+}
+
+fn check_setup(control_type: ControlType, recipient: Recipient) -> Result<(), TransferError> {
+    if control_type != ControlType::Vendor || recipient != Recipient::Interface {
+        return Err(TransferError::Stall);
+    }
+    Ok(())
+}
+
+impl Transport for SimHub {
+    fn control_in(&self, data: ControlIn, _timeout: Duration) -> Result<Vec<u8>, TransferError> {
+        check_setup(data.control_type, data.recipient)?;
+        let mut state = self.state();
+        match data.request {
+            CMD_REG_READ => (0..data.length)
+                .map(|i| {
+                    let (bank, kind) = data
+                        .value
+                        .checked_add(i)
+                        .and_then(lookup)
+                        .ok_or(TransferError::Stall)?;
+                    Ok(state.read(bank, kind))
+                })
+                .collect(),
+            CMD_I2C_READ => {
+                let flags = I2cFlagsAddress::from_bits(data.value);
+                if !flags.is_read() {
+                    return Err(TransferError::Stall);
+                }
+                let mut buf = vec![0; data.length as usize];
+                let ok = state.i2c_begin(flags)?.read(&mut buf);
+                state.i2c_end(flags, ok)?;
+                Ok(buf)
+            }
+            _ => Err(TransferError::Stall),
+        }
+    }
+
+    fn control_out(&self, data: ControlOut<'_>, _timeout: Duration) -> Result<(), TransferError> {
+        check_setup(data.control_type, data.recipient)?;
+        let mut state = self.state();
+        match data.request {
+            CMD_REG_WRITE => {
+                for (i, value) in data.data.iter().enumerate() {
+                    let (bank, kind) = data
+                        .value
+                        .checked_add(i as u16)
+                        .and_then(lookup)
+                        .ok_or(TransferError::Stall)?;
+                    state.write(bank, kind, *value)?;
+                }
+                Ok(())
+            }
+            CMD_I2C_ENTER_PASSTHRU => {
+                state.i2c_passthru = true;
+                Ok(())
+            }
+            CMD_I2C_WRITE => {
+                let flags = I2cFlagsAddress::from_bits(data.value);
+                if flags.is_read() {
+                    return Err(TransferError::Stall);
+                }
+                let ok = state.i2c_begin(flags)?.write(data.data);
+                state.i2c_end(flags, ok)
+            }
+            _ => Err(TransferError::Stall),
+        }
+    }
+}
+
+/// I2C target with a byte-addressed memory and an auto-incrementing 8-bit pointer, like a small EEPROM
+/// or a typical sensor register file.
+///
+/// The first byte written after a start condition sets the pointer, the following bytes are stored into memory.
+/// Clones share the same memory, so one clone can be attached to a [SimHub] and another kept for inspection.
+#[derive(Clone)]
+pub struct MemoryTarget {
+    inner: Arc<Mutex<MemoryInner>>,
+}
+
+struct MemoryInner {
+    memory: Vec<u8>,
+    pointer: usize,
+    pointer_set: bool,
+}
+
+impl MemoryTarget {
+    /// Create a target with size bytes of zeroed memory.
+    pub fn new(size: usize) -> MemoryTarget {
+        MemoryTarget {
+            inner: Arc::new(Mutex::new(MemoryInner {
+                memory: vec![0; size],
+                pointer: 0,
+                pointer_set: false,
+            })),
+        }
+    }
+
+    /// Returns a copy of the memory contents.
+    pub fn memory(&self) -> Vec<u8> {
+        self.inner.lock().unwrap().memory.clone()
+    }
+
+    /// Overwrite memory contents starting at offset.
+    pub fn load(&self, offset: usize, data: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.memory[offset..offset + data.len()].copy_from_slice(data);
+    }
+}
+
+impl I2cTarget for MemoryTarget {
+    fn start(&mut self, is_read: bool) -> bool {
+        if !is_read {
+            self.inner.lock().unwrap().pointer_set = false;
+        }
+        true
+    }
+
+    fn write(&mut self, data: &[u8]) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let len = inner.memory.len();
+        for &byte in data {
+            if inner.pointer_set {
+                let pointer = inner.pointer;
+                inner.memory[pointer] = byte;
+                inner.pointer = (pointer + 1) % len;
+            } else {
+                inner.pointer = byte as usize % len;
+                inner.pointer_set = true;
+            }
+        }
+        true
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let len = inner.memory.len();
+        for byte in buf {
+            *byte = inner.memory[inner.pointer];
+            inner.pointer = (inner.pointer + 1) % len;
+        }
+        true
+    }
+}
@@ -0,0 +1,61 @@
+use nusb::Interface;
+use nusb::MaybeFuture;
+use nusb::transfer::{ControlIn, ControlOut, TransferError};
+use std::future::{Future, IntoFuture, ready};
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Boxed future returned by the async [Transport] methods.
+pub type TransferFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, TransferError>> + Send + 'a>>;
+
+/// Vendor control transfers to the hub feature controller.
+///
+/// [Usb4604](crate::Usb4604) and [I2cBridge](crate::I2cBridge) only ever talk to the IC through this trait,
+/// so any USB stack, remote proxy or in-memory fake can be plugged in with [Usb4604::new](crate::Usb4604::new).
+/// An implementation for nusb [Interface] is provided.
+pub trait Transport: Send + Sync {
+    /// Perform an IN (device-to-host) control transfer and return received data.
+    fn control_in(&self, data: ControlIn, timeout: Duration) -> Result<Vec<u8>, TransferError>;
+
+    /// Perform an OUT (host-to-device) control transfer.
+    fn control_out(&self, data: ControlOut<'_>, timeout: Duration) -> Result<(), TransferError>;
+
+    /// Async version of [control_in](Self::control_in).
+    ///
+    /// Default implementation calls the blocking one, override it if the transport can do async IO,
+    /// otherwise the async API of this crate will block the executor for the duration of each transfer.
+    fn control_in_async(&self, data: ControlIn, timeout: Duration) -> TransferFuture<'_, Vec<u8>> {
+        Box::pin(ready(self.control_in(data, timeout)))
+    }
+
+    /// Async version of [control_out](Self::control_out), see [control_in_async](Self::control_in_async).
+    fn control_out_async<'a>(
+        &'a self,
+        data: ControlOut<'a>,
+        timeout: Duration,
+    ) -> TransferFuture<'a, ()> {
+        Box::pin(ready(self.control_out(data, timeout)))
+    }
+}
+
+impl Transport for Interface {
+    fn control_in(&self, data: ControlIn, timeout: Duration) -> Result<Vec<u8>, TransferError> {
+        Interface::control_in(self, data, timeout).wait()
+    }
+
+    fn control_out(&self, data: ControlOut<'_>, timeout: Duration) -> Result<(), TransferError> {
+        Interface::control_out(self, data, timeout).wait()
+    }
+
+    fn control_in_async(&self, data: ControlIn, timeout: Duration) -> TransferFuture<'_, Vec<u8>> {
+        Box::pin(Interface::control_in(self, data, timeout).into_future())
+    }
+
+    fn control_out_async<'a>(
+        &'a self,
+        data: ControlOut<'a>,
+        timeout: Duration,
+    ) -> TransferFuture<'a, ()> {
+        Box::pin(Interface::control_out(self, data, timeout).into_future())
+    }
+}
@@ -1,119 +1,740 @@
-use crate::gpio::{Pio, Pull};
+use crate::blocking::{block_on, sleep};
+use crate::builder::{REGISTER_POLICY, TransferPolicy};
+use crate::gpio::{ExtendedPio, PinState, Pins, Pio, Pull, SideEffect};
 use crate::i2c::I2cBridge;
-use crate::{Error, Flex, Input, Level, OpenDrainOutput, PushPullOutput, SmscReg};
-use nusb::Interface;
+use crate::pin_map::{BANKS, PinDescriptor};
+use crate::wait::{BankPoller, DEFAULT_POLL_INTERVAL};
+use crate::{
+    ChipVariant, DeviceFilter, Error, Flex, HubTopology, I2cBridgeBuilder, Input, Level,
+    OpenDrainOutput, PushPullOutput, SafeState, SmscReg, Transport, Usb4604Builder, Usb4604Info,
+};
 use nusb::MaybeFuture;
 use nusb::transfer::{ControlIn, ControlOut, ControlType, Recipient, TransferError};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 
+/// Handle to the IC, cheap to clone, all clones share the same underlying device.
+///
+/// Usb4604 is `Send` and `Sync`, as are pins and [I2cBridge], so they can be moved to or shared between threads
+/// and tasks freely. Read-modify-write of a register is atomic across all clones, so pins of the same bank can be
+/// used from different threads without losing each other's updates.
 #[derive(Clone)]
 pub struct Usb4604 {
-    interface: Interface,
+    shared: Arc<Shared>,
 }
 
-const CMD_REG_WRITE: u8 = 0x03;
-const CMD_REG_READ: u8 = 0x04;
+struct Shared {
+    transport: RwLock<Arc<dyn Transport>>,
+    info: Option<Usb4604Info>,
+    reconnect: Mutex<Option<Reconnect>>,
+    /// Configuration of all live pin handles, replayed after reconnect. Also serves as the list of claimed pins.
+    pins: Mutex<BTreeMap<u64, PinState>>,
+    /// Number of live [I2cBridge] handles, only changed while holding the pins lock.
+    i2c_bridges: AtomicUsize,
+    next_pin_id: AtomicU64,
+    i2c_passthru: AtomicBool,
+    /// Input register pollers of pins being waited on, keyed by register address.
+    pollers: Mutex<HashMap<u16, Arc<BankPoller>>>,
+    poll_interval: Mutex<Duration>,
+    chip_variant: Mutex<ChipVariant>,
+    /// Held for the duration of a read-modify-write, see [Usb4604::modify_addr_async].
+    register_lock: async_lock::Mutex<()>,
+    safe_state: Mutex<Option<SafeState>>,
+    /// Last known values of GPIO configuration registers, None if the cache is disabled.
+    shadow: Mutex<Option<HashMap<u16, u8>>>,
+    /// Timeout and retries of register reads and writes, see [Usb4604Builder].
+    register_policy: Mutex<TransferPolicy>,
+}
+
+type Reopen = dyn Fn(Duration) -> Result<Arc<dyn Transport>, Error> + Send + Sync;
+
+struct Reconnect {
+    timeout: Duration,
+    reopen: Box<Reopen>,
+}
+
+pub(crate) const CMD_REG_WRITE: u8 = 0x03;
+pub(crate) const CMD_REG_READ: u8 = 0x04;
 
-const VENDOR_SMSC: u16 = 0x0424;
-const PRODUCT_BRIDGE_DEV: u16 = 0x2530;
-// const PRODUCT_USB4604_HUB: u16 = 0x4502;
+pub(crate) const VENDOR_SMSC: u16 = 0x0424;
+pub(crate) const PRODUCT_BRIDGE_DEV: u16 = 0x2530;
 
 impl Usb4604 {
-    /// Create Usb4604 from an already open nusb USB [Interface](Interface).
-    pub fn new(interface: Interface) -> Usb4604 {
-        Usb4604 { interface }
+    /// Create Usb4604 from any [Transport], e.g. an already open nusb USB [Interface](nusb::Interface).
+    pub fn new(transport: impl Transport + 'static) -> Usb4604 {
+        Usb4604::from_parts(Arc::new(transport), None)
+    }
+
+    pub(crate) fn from_parts(transport: Arc<dyn Transport>, info: Option<Usb4604Info>) -> Usb4604 {
+        let chip_variant = info
+            .as_ref()
+            .and_then(Usb4604Info::chip_variant)
+            .unwrap_or(ChipVariant::Usb4604);
+        Usb4604 {
+            shared: Arc::new(Shared {
+                transport: RwLock::new(transport),
+                info,
+                reconnect: Mutex::new(None),
+                pins: Mutex::new(BTreeMap::new()),
+                next_pin_id: AtomicU64::new(0),
+                i2c_bridges: AtomicUsize::new(0),
+                i2c_passthru: AtomicBool::new(false),
+                pollers: Mutex::new(HashMap::new()),
+                poll_interval: Mutex::new(DEFAULT_POLL_INTERVAL),
+                chip_variant: Mutex::new(chip_variant),
+                register_lock: async_lock::Mutex::new(()),
+                safe_state: Mutex::new(None),
+                shadow: Mutex::new(None),
+                register_policy: Mutex::new(REGISTER_POLICY),
+            }),
+        }
+    }
+
+    /// Create a [Usb4604Builder] to configure timeout and retries of register access.
+    pub fn builder() -> Usb4604Builder {
+        Usb4604Builder::new()
+    }
+
+    pub(crate) fn set_register_policy(&self, policy: TransferPolicy) {
+        *self.shared.register_policy.lock().unwrap() = policy;
+    }
+
+    /// Enumeration data of the device, if it was opened through [list](Self::list) or [filter](Self::filter).
+    pub fn info(&self) -> Option<&Usb4604Info> {
+        self.shared.info.as_ref()
+    }
+
+    /// Chip this feature controller belongs to, only pins that exist on it can be opened.
+    ///
+    /// Detected from the hub product id when opened through [list](Self::list) or [filter](Self::filter),
+    /// USB4604 is assumed otherwise or if the hub was not recognized.
+    pub fn chip_variant(&self) -> ChipVariant {
+        *self.shared.chip_variant.lock().unwrap()
+    }
+
+    /// Override detected chip variant, e.g. for a hub configured with a custom PID.
+    pub fn set_chip_variant(&self, variant: ChipVariant) {
+        *self.shared.chip_variant.lock().unwrap() = variant;
+    }
+
+    /// Returns pin description on the current chip variant.
+    pub(crate) fn pin(&self, pio: ExtendedPio) -> Result<&'static PinDescriptor, Error> {
+        self.chip_variant()
+            .pin(pio)
+            .ok_or(Error::PinNotAvailable(pio))
+    }
+
+    /// Enable or disable (with None) automatic reconnect, disabled by default.
+    ///
+    /// When a transfer fails because the device was disconnected, e.g. after a hub reset or a power cycle,
+    /// the same physical device is waited for up to timeout and re-opened.
+    /// Then the last known direction, output level and pull configuration of all live pin handles is applied again,
+    /// I2C pass-through is re-entered if it was used, and the failed register operation is retried once.
+    /// An I2C transaction is not resent, the bridge lost its part on the bus, it fails with
+    /// [Disconnected](TransferError::Disconnected) and the next one goes to the new connection.
+    /// All clones of this Usb4604 and all pin handles switch to the new connection.
+    ///
+    /// Reconnecting blocks the calling thread, also when it happens in the async API.
+    ///
+    /// Only available when the device was opened through [list](Self::list) or [filter](Self::filter),
+    /// use [set_reconnect_with](Self::set_reconnect_with) for other transports.
+    pub fn set_reconnect(&self, timeout: Option<Duration>) -> Result<(), Error> {
+        let Some(timeout) = timeout else {
+            *self.shared.reconnect.lock().unwrap() = None;
+            return Ok(());
+        };
+        let info = self
+            .info()
+            .ok_or(Error::Other("device location is unknown"))?;
+        let mut filter = DeviceFilter::new()
+            .bus_id(info.bus_id())
+            .port_chain(info.port_chain());
+        if let Some(serial_number) = info.serial_number() {
+            filter = filter.serial_number(serial_number);
+        }
+        self.set_reconnect_with(timeout, move |timeout| {
+            Ok(filter.wait_open(timeout)?.transport())
+        });
+        Ok(())
+    }
+
+    /// Enable automatic reconnect with a custom way of opening the device again, see [set_reconnect](Self::set_reconnect).
+    ///
+    /// reopen is called with timeout and must return a fresh [Transport] to the same device, or an error if it did
+    /// not come back in time. Useful for transports that are not opened through [filter](Self::filter),
+    /// e.g. a remote proxy. Disable with `set_reconnect(None)`.
+    pub fn set_reconnect_with(
+        &self,
+        timeout: Duration,
+        reopen: impl Fn(Duration) -> Result<Arc<dyn Transport>, Error> + Send + Sync + 'static,
+    ) {
+        *self.shared.reconnect.lock().unwrap() = Some(Reconnect {
+            timeout,
+            reopen: Box::new(reopen),
+        });
+    }
+
+    /// Set how often GPIO input registers are read while waiting for a pin level or edge, 10 ms by default.
+    ///
+    /// Pins of the same register bank share one poller, so waiting on several pins does not multiply USB traffic.
+    /// Pulses shorter than the interval may be missed.
+    pub fn set_poll_interval(&self, interval: Duration) {
+        *self.shared.poll_interval.lock().unwrap() = interval;
+    }
+
+    pub(crate) fn poll_interval(&self) -> Duration {
+        *self.shared.poll_interval.lock().unwrap()
+    }
+
+    /// Enable or disable write-through cache of GPIO Dir, Output, PullUp and PullDown registers, disabled by default.
+    ///
+    /// When enabled, these registers are read from the device only once and then served from memory,
+    /// so read-modify-write operations like [Flex::toggle] take one transfer instead of two.
+    /// Writes always go to the device. Input registers are never cached.
+    /// The cache is shared by all clones and is cleared after reconnect.
+    ///
+    /// Only enable it if nothing else (another process, EEPROM or SMBus configuration) changes these registers,
+    /// otherwise call [invalidate](Self::invalidate) or [sync](Self::sync) when that might have happened.
+    /// Bits that are not writable on a particular IC (see [probe_pins](Self::probe_pins)) read back as written.
+    pub fn set_shadow_cache(&self, enabled: bool) {
+        *self.shared.shadow.lock().unwrap() = enabled.then(HashMap::new);
+    }
+
+    /// Returns true if the register cache is enabled.
+    pub fn is_shadow_cache_enabled(&self) -> bool {
+        self.shared.shadow.lock().unwrap().is_some()
+    }
+
+    /// Forget all cached register values, they will be read from the device on next access.
+    pub fn invalidate(&self) {
+        if let Some(shadow) = self.shared.shadow.lock().unwrap().as_mut() {
+            shadow.clear();
+        }
+    }
+
+    /// Re-read all cached registers from the device right away, does nothing if the cache is disabled.
+    pub fn sync(&self) -> Result<(), TransferError> {
+        block_on(self.sync_async())
+    }
+
+    /// Async version of [sync](Self::sync).
+    pub async fn sync_async(&self) -> Result<(), TransferError> {
+        if !self.is_shadow_cache_enabled() {
+            return Ok(());
+        }
+        self.invalidate();
+        for addr in self
+            .chip_variant()
+            .banks()
+            .flat_map(|bank| bank.configuration())
+        {
+            self.read_addr_async(addr).await?;
+        }
+        Ok(())
+    }
+
+    /// Store (or forget with None) register value if the cache is enabled and the register is cacheable.
+    fn update_shadow(&self, addr: u16, value: Option<u8>) {
+        let mut shadow = self.shared.shadow.lock().unwrap();
+        let Some(shadow) = shadow.as_mut() else {
+            return;
+        };
+        if !BANKS
+            .iter()
+            .any(|bank| bank.configuration().contains(&addr))
+        {
+            return;
+        }
+        match value {
+            Some(value) => shadow.insert(addr, value),
+            None => shadow.remove(&addr),
+        };
+    }
+
+    pub(crate) fn safe_state(&self) -> &Mutex<Option<SafeState>> {
+        &self.shared.safe_state
+    }
+
+    pub(crate) fn pollers(&self) -> &Mutex<HashMap<u16, Arc<BankPoller>>> {
+        &self.shared.pollers
+    }
+
+    /// Enumerate devices connected to the downstream ports of this IC's hub.
+    ///
+    /// Only available when the device was opened through [list](Self::list) or [filter](Self::filter).
+    /// Use [DeviceFilter::upstream_of] to go the other way and find the hub that a given device is plugged into.
+    pub fn topology(&self) -> Result<HubTopology, Error> {
+        self.info()
+            .ok_or(Error::Other("device location is unknown"))?
+            .topology()
+    }
+
+    /// Enumerate all connected USB4604 feature controllers, ordered by bus and port chain.
+    pub fn list() -> Result<Vec<Usb4604Info>, Error> {
+        let devices: Vec<_> = nusb::list_devices().wait()?.collect();
+        Ok(Usb4604Info::from_devices(&devices))
+    }
+
+    /// Create a [DeviceFilter] to select one of several connected devices.
+    pub fn filter() -> DeviceFilter {
+        DeviceFilter::new()
     }
 
     /// Enumerate, and open the first and only available device.
     /// Error is returned if more than one device is found.
     ///
-    /// If multiple device support is required, use [new](Self::new) and implement a desired filtering system.
+    /// If multiple device support is required, use [open_by_serial](Self::open_by_serial),
+    /// [open_by_port_path](Self::open_by_port_path) or [filter](Self::filter).
     pub fn open_auto() -> Result<Self, Error> {
-        let di = nusb::list_devices()
-            .wait()?
-            .find(|d| d.vendor_id() == VENDOR_SMSC && d.product_id() == PRODUCT_BRIDGE_DEV);
-        let Some(di) = di else {
-            return Err(Error::NoDevicesFound);
-        };
-        let device = di.open().wait()?;
-        let interface = device.claim_interface(0).wait()?;
-        Ok(Self { interface })
+        DeviceFilter::new().open()
+    }
+
+    /// Open the device with a given serial number.
+    pub fn open_by_serial(serial_number: &str) -> Result<Self, Error> {
+        DeviceFilter::new().serial_number(serial_number).open()
+    }
+
+    /// Open the device connected to a given physical port, see [Usb4604Info::port_chain].
+    pub fn open_by_port_path(bus_id: &str, port_chain: &[u8]) -> Result<Self, Error> {
+        DeviceFilter::new()
+            .bus_id(bus_id)
+            .port_chain(port_chain)
+            .open()
     }
 
     /// Read pin mode from the IC and create a [Flex](Flex) pin.
+    ///
+    /// Every pin can only be held by one handle at a time, [Error::PinInUse] is returned otherwise.
     pub fn gpio(&self, pio: Pio) -> Result<Flex, Error> {
-        Ok(Flex::init_get_mode(self.clone(), pio)?)
+        block_on(self.gpio_async(pio))
+    }
+
+    /// Async version of [gpio](Self::gpio).
+    pub async fn gpio_async(&self, pio: Pio) -> Result<Flex, Error> {
+        Flex::init_get_mode(self.clone(), self.pin(pio.into())?).await
+    }
+
+    /// Read pin mode from the IC and create a [Flex](Flex) pin for any IO in the register map.
+    ///
+    /// Every [side effect](ExtendedPio::side_effects) of the pin must be listed in `acknowledged`,
+    /// otherwise [Error::UnacknowledgedSideEffect] is returned and the pin is not touched.
+    /// Pins that are in [Pio] open without acknowledgement, same as with [gpio](Self::gpio).
+    pub fn gpio_extended(
+        &self,
+        pio: ExtendedPio,
+        acknowledged: &[SideEffect],
+    ) -> Result<Flex, Error> {
+        block_on(self.gpio_extended_async(pio, acknowledged))
+    }
+
+    /// Async version of [gpio_extended](Self::gpio_extended).
+    pub async fn gpio_extended_async(
+        &self,
+        pio: ExtendedPio,
+        acknowledged: &[SideEffect],
+    ) -> Result<Flex, Error> {
+        let pin = self.pin(pio)?;
+        if let Some(effect) = pin.unacknowledged(acknowledged) {
+            return Err(Error::UnacknowledgedSideEffect(effect));
+        }
+        Flex::init_get_mode(self.clone(), pin).await
+    }
+
+    /// Read modes of all [verified](Pio) pins from the IC and hand out each of them exactly once.
+    ///
+    /// Fails with [Error::PinInUse] if any of them is already held, pins are available again once dropped.
+    pub fn split(&self) -> Result<Pins, Error> {
+        block_on(self.split_async())
+    }
+
+    /// Async version of [split](Self::split).
+    pub async fn split_async(&self) -> Result<Pins, Error> {
+        let optional = async |pio: Pio| match self.chip_variant().pin(pio.into()) {
+            Some(_) => self.gpio_async(pio).await.map(Some),
+            None => Ok(None),
+        };
+        Ok(Pins {
+            pio0: self.gpio_async(Pio::Pio0).await?,
+            pio1: self.gpio_async(Pio::Pio1).await?,
+            pio3: self.gpio_async(Pio::Pio3).await?,
+            pio8: self.gpio_async(Pio::Pio8).await?,
+            pio9: self.gpio_async(Pio::Pio9).await?,
+            pio10: self.gpio_async(Pio::Pio10).await?,
+            pio19: optional(Pio::Pio19).await?,
+            pio20: optional(Pio::Pio20).await?,
+        })
     }
 
     /// Optionally set initial level, configure pin as output and return [PushPullOutput].
     pub fn output(&self, pio: Pio, initial: Option<Level>) -> Result<PushPullOutput, Error> {
-        let flex = Flex::init_ignore_mode(self.clone(), pio);
-        Ok(flex.into_output(initial)?)
+        block_on(self.output_async(pio, initial))
+    }
+
+    /// Async version of [output](Self::output).
+    pub async fn output_async(
+        &self,
+        pio: Pio,
+        initial: Option<Level>,
+    ) -> Result<PushPullOutput, Error> {
+        let flex = Flex::init_ignore_mode(self.clone(), self.pin(pio.into())?).await?;
+        flex.into_output_async(initial).await
     }
 
     /// Configure pin as input, optionally enable pull-up or pull-down resistor and return [Input].
     pub fn input(&self, pio: Pio, pull: Pull) -> Result<Input, Error> {
-        let flex = Flex::init_ignore_mode(self.clone(), pio);
-        Ok(flex.into_input(pull)?)
+        block_on(self.input_async(pio, pull))
+    }
+
+    /// Async version of [input](Self::input).
+    pub async fn input_async(&self, pio: Pio, pull: Pull) -> Result<Input, Error> {
+        let flex = Flex::init_ignore_mode(self.clone(), self.pin(pio.into())?).await?;
+        flex.into_input_async(pull).await
     }
 
     /// Configure pin as input + open-drain output mode, optionally enable pull-up or pull-down resistor and return [OpenDrainOutput].
     pub fn open_drain(&self, pio: Pio, pull: Pull) -> Result<OpenDrainOutput, Error> {
-        let flex = Flex::init_ignore_mode(self.clone(), pio);
-        Ok(flex.into_open_drain_output(pull)?)
+        block_on(self.open_drain_async(pio, pull))
+    }
+
+    /// Async version of [open_drain](Self::open_drain).
+    pub async fn open_drain_async(&self, pio: Pio, pull: Pull) -> Result<OpenDrainOutput, Error> {
+        let flex = Flex::init_ignore_mode(self.clone(), self.pin(pio.into())?).await?;
+        flex.into_open_drain_output_async(pull).await
     }
 
     pub fn read_reg<R: SmscReg>(&self) -> Result<R, TransferError> {
+        block_on(self.read_reg_async())
+    }
+
+    /// Async version of [read_reg](Self::read_reg).
+    pub async fn read_reg_async<R: SmscReg>(&self) -> Result<R, TransferError> {
+        Ok(R::from_value(self.read_addr_async(R::ADDR).await?))
+    }
+
+    /// Read a register by address, e.g. one that has no type in [usb4604_reg](crate::usb4604_reg).
+    ///
+    /// Served from the [shadow cache](Self::set_shadow_cache) if possible.
+    pub fn read_addr(&self, addr: u16) -> Result<u8, TransferError> {
+        block_on(self.read_addr_async(addr))
+    }
+
+    /// Async version of [read_addr](Self::read_addr).
+    pub async fn read_addr_async(&self, addr: u16) -> Result<u8, TransferError> {
+        let cached = self
+            .shared
+            .shadow
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|shadow| shadow.get(&addr).copied());
+        match cached {
+            Some(value) => Ok(value),
+            None => self.read_addr_uncached_async(addr).await,
+        }
+    }
+
+    /// Read a register by address from the device, bypassing the cache, but updating it.
+    pub(crate) async fn read_addr_uncached_async(&self, addr: u16) -> Result<u8, TransferError> {
+        let data = ControlIn {
+            control_type: ControlType::Vendor,
+            recipient: Recipient::Interface,
+            request: CMD_REG_READ,
+            value: addr,
+            index: 0,
+            length: 1,
+        };
         let read = self
-            .interface
-            .control_in(
-                ControlIn {
-                    control_type: ControlType::Vendor,
-                    recipient: Recipient::Interface,
-                    request: CMD_REG_READ,
-                    value: R::ADDR,
-                    index: 0,
-                    length: 1,
-                },
-                Duration::from_millis(500),
-            )
-            .wait()?;
-        Ok(R::from_value(read[0]))
+            .retry_register(|timeout| self.control_in(data, timeout))
+            .await?;
+        // a transport may return less than asked for
+        let value = read.first().copied().ok_or(TransferError::Fault)?;
+        self.update_shadow(addr, Some(value));
+        Ok(value)
     }
 
     pub fn write_reg<R: SmscReg>(&mut self, value: R) -> Result<(), TransferError> {
-        self.interface
-            .control_out(
-                ControlOut {
-                    control_type: ControlType::Vendor,
-                    recipient: Recipient::Interface,
-                    request: CMD_REG_WRITE,
-                    value: R::ADDR,
-                    index: 0,
-                    data: &[value.value()],
-                },
-                Duration::from_millis(500),
-            )
-            .wait()?;
-        Ok(())
+        block_on(self.write_reg_async(value))
     }
 
-    pub fn modify_reg<R: SmscReg, F: FnMut(&mut R)>(
+    /// Async version of [write_reg](Self::write_reg).
+    pub async fn write_reg_async<R: SmscReg>(&mut self, value: R) -> Result<(), TransferError> {
+        self.write_addr_async(R::ADDR, value.value()).await
+    }
+
+    /// Write a register by address, e.g. one that has no type in [usb4604_reg](crate::usb4604_reg).
+    pub fn write_addr(&self, addr: u16, value: u8) -> Result<(), TransferError> {
+        block_on(self.write_addr_async(addr, value))
+    }
+
+    /// Async version of [write_addr](Self::write_addr).
+    pub async fn write_addr_async(&self, addr: u16, value: u8) -> Result<(), TransferError> {
+        let data = [value];
+        let data = ControlOut {
+            control_type: ControlType::Vendor,
+            recipient: Recipient::Interface,
+            request: CMD_REG_WRITE,
+            value: addr,
+            index: 0,
+            data: &data,
+        };
+        let result = self
+            .retry_register(|timeout| self.control_out(data, timeout))
+            .await;
+        // on failure it is unknown whether the write made it to the device
+        self.update_shadow(addr, result.is_ok().then_some(value));
+        result
+    }
+
+    /// Repeat a register transfer according to the [policy](Usb4604Builder), writing the same value twice is harmless.
+    async fn retry_register<T, F: Future<Output = Result<T, TransferError>>>(
+        &self,
+        mut transfer: impl FnMut(Duration) -> F,
+    ) -> Result<T, TransferError> {
+        let policy = *self.shared.register_policy.lock().unwrap();
+        let mut retry = 0;
+        loop {
+            match transfer(policy.timeout).await {
+                // disconnect was already handled by reconnect
+                Err(e)
+                    if retry < policy.retries
+                        && !matches!(
+                            e,
+                            TransferError::Disconnected | TransferError::InvalidArgument
+                        ) =>
+                {
+                    sleep(policy.backoff(retry)).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Read register, modify it with f and write back if the value changed.
+    ///
+    /// Atomic with respect to other modifications through any clone of this Usb4604,
+    /// but not to other processes or plain [write_reg](Self::write_reg) calls.
+    pub fn modify_reg<R: SmscReg, F: FnMut(&mut R)>(&mut self, f: F) -> Result<(), TransferError> {
+        block_on(self.modify_reg_async(f))
+    }
+
+    /// Async version of [modify_reg](Self::modify_reg).
+    pub async fn modify_reg_async<R: SmscReg, F: FnMut(&mut R)>(
         &mut self,
         mut f: F,
     ) -> Result<(), TransferError> {
-        let mut value: R = self.read_reg()?;
+        let _guard = self.lock_registers().await;
+        let mut value: R = self.read_reg_async().await?;
         let old_value = value.value();
         f(&mut value);
         if old_value != value.value() {
-            self.write_reg(value)?;
+            self.write_addr_async(R::ADDR, value.value()).await?;
+        }
+        Ok(())
+    }
+
+    /// Read-modify-write of a register by address, write is skipped if the value did not change.
+    ///
+    /// Other read-modify-writes through any clone wait until this one is done.
+    pub(crate) async fn modify_addr_async(
+        &self,
+        addr: u16,
+        f: impl FnOnce(u8) -> u8,
+    ) -> Result<(), TransferError> {
+        let _guard = self.lock_registers().await;
+        let old_value = self.read_addr_async(addr).await?;
+        let value = f(old_value);
+        if old_value != value {
+            self.write_addr_async(addr, value).await?;
         }
         Ok(())
     }
 
-    /// Enable I2C bridging and return [I2cBridge]
+    /// Exclude read-modify-writes from all clones until the guard is dropped.
+    ///
+    /// Must not be held while calling [modify_addr_async](Self::modify_addr_async), it would deadlock.
+    pub(crate) async fn lock_registers(&self) -> async_lock::MutexGuard<'_, ()> {
+        self.shared.register_lock.lock().await
+    }
+
+    /// Returns true if a read-modify-write is in progress, through any clone.
+    ///
+    /// Synchronous drop code must not wait for the lock then, the holder might be paused on the same thread.
+    pub(crate) fn is_register_lock_held(&self) -> bool {
+        self.shared.register_lock.try_lock().is_none()
+    }
+
+    /// Enable I2C bridging and return [I2cBridge] with default settings, see [i2c_bridge_builder](Self::i2c_bridge_builder).
     pub fn i2c_bridge(&self) -> Result<I2cBridge, Error> {
-        let i2c = I2cBridge::init(self.interface.clone())?;
-        Ok(i2c)
+        block_on(self.i2c_bridge_async())
+    }
+
+    /// Async version of [i2c_bridge](Self::i2c_bridge).
+    pub async fn i2c_bridge_async(&self) -> Result<I2cBridge, Error> {
+        self.i2c_bridge_builder().build_async().await
+    }
+
+    /// Create an [I2cBridgeBuilder] to configure timeout and retries before enabling I2C bridging.
+    pub fn i2c_bridge_builder(&self) -> I2cBridgeBuilder {
+        I2cBridgeBuilder::new(self.clone())
+    }
+
+    /// Self-contained transfer, e.g. register access: on disconnect, reconnect and send it again.
+    pub(crate) async fn control_in(
+        &self,
+        data: ControlIn,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, TransferError> {
+        let transport = self.transport();
+        match transport.control_in_async(data, timeout).await {
+            Err(TransferError::Disconnected) => {
+                self.reconnect(&transport)?;
+                self.transport().control_in_async(data, timeout).await
+            }
+            r => r,
+        }
+    }
+
+    pub(crate) async fn control_out(
+        &self,
+        data: ControlOut<'_>,
+        timeout: Duration,
+    ) -> Result<(), TransferError> {
+        let transport = self.transport();
+        match transport.control_out_async(data, timeout).await {
+            Err(TransferError::Disconnected) => {
+                self.reconnect(&transport)?;
+                self.transport().control_out_async(data, timeout).await
+            }
+            r => r,
+        }
+    }
+
+    /// Transfer that depends on the state of previous ones, e.g. an I2C transaction chunk:
+    /// on disconnect, reconnect for the transfers to come, but do not resend it.
+    pub(crate) async fn control_in_once(
+        &self,
+        data: ControlIn,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, TransferError> {
+        let transport = self.transport();
+        let result = transport.control_in_async(data, timeout).await;
+        if let Err(TransferError::Disconnected) = result {
+            // fails either way, reconnect error is the same
+            let _ = self.reconnect(&transport);
+        }
+        result
+    }
+
+    /// See [control_in_once](Self::control_in_once).
+    pub(crate) async fn control_out_once(
+        &self,
+        data: ControlOut<'_>,
+        timeout: Duration,
+    ) -> Result<(), TransferError> {
+        let transport = self.transport();
+        let result = transport.control_out_async(data, timeout).await;
+        if let Err(TransferError::Disconnected) = result {
+            let _ = self.reconnect(&transport);
+        }
+        result
+    }
+
+    fn transport(&self) -> Arc<dyn Transport> {
+        self.shared.transport.read().unwrap().clone()
+    }
+
+    /// Replace failed transport with a fresh one and restore pin configuration on it.
+    ///
+    /// Blocks the calling thread, even when called from the async API.
+    fn reconnect(&self, failed: &Arc<dyn Transport>) -> Result<(), TransferError> {
+        // also serializes reconnect attempts from different clones
+        let reconnect = self.shared.reconnect.lock().unwrap();
+        let Some(reconnect) = reconnect.as_ref() else {
+            return Err(TransferError::Disconnected);
+        };
+        if !Arc::ptr_eq(failed, &self.transport()) {
+            // another clone already reconnected
+            return Ok(());
+        }
+        let transport =
+            (reconnect.reopen)(reconnect.timeout).map_err(|_| TransferError::Disconnected)?;
+        // replay through a separate handle, so that a failure does not recurse into reconnect
+        let mut fresh = Usb4604::from_parts(transport.clone(), None);
+        fresh.set_register_policy(*self.shared.register_policy.lock().unwrap());
+        fresh.set_chip_variant(self.chip_variant());
+        let pins: Vec<PinState> = self.shared.pins.lock().unwrap().values().copied().collect();
+        block_on(async {
+            for pin in &pins {
+                pin.replay(&mut fresh)
+                    .await
+                    .map_err(|_| TransferError::Disconnected)?;
+            }
+            if self.shared.i2c_passthru.load(Ordering::Relaxed) {
+                let timeout = self.shared.register_policy.lock().unwrap().timeout;
+                I2cBridge::enter_passthru(&fresh, timeout).await?;
+            }
+            Ok::<_, TransferError>(())
+        })?;
+        *self.shared.transport.write().unwrap() = transport;
+        // device might have been power cycled, pins that are not replayed are back to defaults
+        self.invalidate();
+        Ok(())
+    }
+
+    /// Returns true if both handles are clones of the same device.
+    pub(crate) fn is_same_device(&self, other: &Usb4604) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+
+    pub(crate) fn set_i2c_passthru(&self) {
+        self.shared.i2c_passthru.store(true, Ordering::Relaxed);
+    }
+
+    /// Claim the pin and track its configuration for replay after reconnect,
+    /// returns an id to update and release it later.
+    pub(crate) fn claim_pin(&self, state: PinState) -> Result<u64, Error> {
+        let mut pins = self.shared.pins.lock().unwrap();
+        let pio = state.pin().pio;
+        if pins.values().any(|other| other.pin().pio == pio) {
+            return Err(Error::PinInUse(pio));
+        }
+        if self.is_i2c_pin(state.pin()) && self.shared.i2c_bridges.load(Ordering::Relaxed) > 0 {
+            return Err(Error::PinUsedByI2c(pio));
+        }
+        let id = self.shared.next_pin_id.fetch_add(1, Ordering::Relaxed);
+        pins.insert(id, state);
+        Ok(id)
+    }
+
+    /// Fails if any of the I2C master pins is claimed as GPIO, call [release_i2c](Self::release_i2c) when done.
+    pub(crate) fn claim_i2c(&self) -> Result<(), Error> {
+        let pins = self.shared.pins.lock().unwrap();
+        if let Some(pin) = pins.values().find(|state| self.is_i2c_pin(state.pin())) {
+            return Err(Error::PinUsedByI2c(pin.pin().pio));
+        }
+        self.shared.i2c_bridges.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub(crate) fn release_i2c(&self) {
+        let _pins = self.shared.pins.lock().unwrap();
+        self.shared.i2c_bridges.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn is_i2c_pin(&self, pin: &PinDescriptor) -> bool {
+        pin.side_effects.contains(&SideEffect::DisablesI2c)
+    }
+
+    pub(crate) fn update_pin(&self, id: u64, f: impl FnOnce(&mut PinState)) {
+        if let Some(state) = self.shared.pins.lock().unwrap().get_mut(&id) {
+            f(state);
+        }
+    }
+
+    pub(crate) fn release_pin(&self, id: u64) {
+        self.shared.pins.lock().unwrap().remove(&id);
     }
 }
@@ -0,0 +1,165 @@
+use crate::blocking::block_on;
+use crate::pin_map::PinDescriptor;
+use crate::{Error, Usb4604};
+use nusb::transfer::TransferError;
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+pub(crate) const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What a pin is waiting for, see [Wait](embedded_hal_async::digital::Wait).
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Condition {
+    High,
+    Low,
+    RisingEdge,
+    FallingEdge,
+    AnyEdge,
+}
+
+impl Condition {
+    /// Returns true if the condition is met by the pin going from last to now, last is None for the first sample.
+    fn is_met(self, last: Option<bool>, now: bool) -> bool {
+        match (self, last) {
+            (Condition::High, _) => now,
+            (Condition::Low, _) => !now,
+            (_, None) => false,
+            (Condition::RisingEdge, Some(last)) => !last && now,
+            (Condition::FallingEdge, Some(last)) => last && !now,
+            (Condition::AnyEdge, Some(last)) => last != now,
+        }
+    }
+}
+
+/// Wait until the pin input satisfies the condition.
+pub(crate) async fn wait_for(
+    usb4604: &Usb4604,
+    pin: &PinDescriptor,
+    condition: Condition,
+) -> Result<(), Error> {
+    let subscription = BankPoller::subscribe(usb4604, pin.registers.input, pin.mask(), condition);
+    poll_fn(|cx| subscription.poll(cx)).await?;
+    Ok(())
+}
+
+/// Reads one input register on a background thread for as long as any pin of that bank is being waited on.
+///
+/// There is at most one poller per register bank, registered in [Usb4604], so waiting on several pins
+/// does not multiply USB traffic. Every sample is checked against every waiter, so no edge is missed
+/// between two samples, even if the waiting task is not polled in time.
+pub(crate) struct BankPoller {
+    waiters: Mutex<Waiters>,
+}
+
+#[derive(Default)]
+struct Waiters {
+    next_id: u64,
+    map: HashMap<u64, Waiter>,
+}
+
+struct Waiter {
+    mask: u8,
+    condition: Condition,
+    last: Option<bool>,
+    result: Option<Result<(), TransferError>>,
+    waker: Option<Waker>,
+}
+
+struct Subscription {
+    poller: Arc<BankPoller>,
+    id: u64,
+}
+
+impl BankPoller {
+    fn subscribe(usb4604: &Usb4604, addr: u16, mask: u8, condition: Condition) -> Subscription {
+        // registry is always locked before waiters, see run()
+        let mut pollers = usb4604.pollers().lock().unwrap();
+        let poller = pollers
+            .entry(addr)
+            .or_insert_with(|| {
+                let poller = Arc::new(BankPoller {
+                    waiters: Mutex::new(Waiters::default()),
+                });
+                let usb4604 = usb4604.clone();
+                let this = poller.clone();
+                thread::spawn(move || this.run(usb4604, addr));
+                poller
+            })
+            .clone();
+        let mut waiters = poller.waiters.lock().unwrap();
+        let id = waiters.next_id;
+        waiters.next_id += 1;
+        waiters.map.insert(
+            id,
+            Waiter {
+                mask,
+                condition,
+                last: None,
+                result: None,
+                waker: None,
+            },
+        );
+        drop(waiters);
+        Subscription { poller, id }
+    }
+
+    fn run(self: Arc<Self>, usb4604: Usb4604, addr: u16) {
+        loop {
+            {
+                let mut pollers = usb4604.pollers().lock().unwrap();
+                if self.waiters.lock().unwrap().map.is_empty() {
+                    pollers.remove(&addr);
+                    return;
+                }
+            }
+            let sample = block_on(usb4604.read_addr_async(addr));
+            let mut waiters = self.waiters.lock().unwrap();
+            for waiter in waiters.map.values_mut().filter(|w| w.result.is_none()) {
+                match sample {
+                    Ok(bits) => {
+                        let now = bits & waiter.mask != 0;
+                        let is_met = waiter.condition.is_met(waiter.last, now);
+                        waiter.last = Some(now);
+                        if !is_met {
+                            continue;
+                        }
+                        waiter.result = Some(Ok(()));
+                    }
+                    Err(e) => waiter.result = Some(Err(e)),
+                }
+                if let Some(waker) = waiter.waker.take() {
+                    waker.wake();
+                }
+            }
+            drop(waiters);
+            thread::sleep(usb4604.poll_interval());
+        }
+    }
+}
+
+impl Subscription {
+    fn poll(&self, cx: &mut Context<'_>) -> Poll<Result<(), TransferError>> {
+        let mut waiters = self.poller.waiters.lock().unwrap();
+        let waiter = waiters
+            .map
+            .get_mut(&self.id)
+            .expect("waiter is only removed on drop");
+        match waiter.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                waiter.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.poller.waiters.lock().unwrap().map.remove(&self.id);
+    }
+}
@@ -0,0 +1,39 @@
+use strum::IntoEnumIterator;
+use usb4604::sim::SimHub;
+use usb4604::{ChipVariant, Error, ExtendedPio, Pio, Usb4604};
+
+#[test]
+fn product_id_round_trip() {
+    for variant in ChipVariant::iter() {
+        assert_eq!(
+            ChipVariant::from_hub_product_id(variant.hub_product_id()),
+            Some(variant)
+        );
+    }
+    assert_eq!(
+        ChipVariant::from_hub_product_id(0x4502),
+        Some(ChipVariant::Usb4604)
+    );
+    assert_eq!(ChipVariant::from_hub_product_id(0x2530), None);
+}
+
+#[test]
+fn pins_of_missing_ports_are_not_available() {
+    // PIO19 and PIO20 are the over-current sense inputs of ports 3 and 4
+    let pins = |variant: ChipVariant| {
+        [Pio::Pio19, Pio::Pio20].map(|pio| variant.pin(pio.into()).is_some())
+    };
+    assert_eq!(ChipVariant::Usb2532.downstream_ports(), 2);
+    assert_eq!(pins(ChipVariant::Usb2532), [false, false]);
+    assert_eq!(ChipVariant::Usb2533.downstream_ports(), 3);
+    assert_eq!(pins(ChipVariant::Usb2533), [true, false]);
+    assert_eq!(ChipVariant::Usb4604.downstream_ports(), 4);
+    assert_eq!(pins(ChipVariant::Usb4604), [true, true]);
+
+    let usb4604 = Usb4604::new(SimHub::new());
+    usb4604.set_chip_variant(ChipVariant::Usb2532);
+    assert!(matches!(
+        usb4604.gpio(Pio::Pio19),
+        Err(Error::PinNotAvailable(ExtendedPio::Pio19))
+    ));
+}
@@ -0,0 +1,72 @@
+use usb4604::sim::SimHub;
+use usb4604::{Error, ExtendedPio, Pio, Pull, SideEffect, Usb4604};
+
+#[test]
+fn pin_can_be_held_once() {
+    let usb4604 = Usb4604::new(SimHub::new());
+    let pio0 = usb4604.gpio(Pio::Pio0).unwrap();
+    assert!(matches!(
+        usb4604.gpio(Pio::Pio0),
+        Err(Error::PinInUse(ExtendedPio::Pio0))
+    ));
+    // claims are shared by all clones
+    assert!(matches!(
+        usb4604.clone().input(Pio::Pio0, Pull::None),
+        Err(Error::PinInUse(ExtendedPio::Pio0))
+    ));
+    drop(pio0);
+    assert!(usb4604.gpio(Pio::Pio0).is_ok());
+}
+
+#[test]
+fn split_hands_out_every_pin_once() {
+    let usb4604 = Usb4604::new(SimHub::new());
+    let pins = usb4604.split().unwrap();
+    assert!(pins.pio19.is_some() && pins.pio20.is_some());
+    assert!(matches!(usb4604.split(), Err(Error::PinInUse(_))));
+    assert!(matches!(
+        usb4604.gpio(Pio::Pio10),
+        Err(Error::PinInUse(ExtendedPio::Pio10))
+    ));
+    drop(pins);
+    assert!(usb4604.split().is_ok());
+}
+
+#[test]
+fn i2c_pin_and_bridge_exclude_each_other() {
+    let usb4604 = Usb4604::new(SimHub::new());
+    let acknowledged = [SideEffect::DisablesI2c, SideEffect::Unverified];
+
+    let pio2 = usb4604
+        .gpio_extended(ExtendedPio::Pio2, &acknowledged)
+        .unwrap();
+    assert!(matches!(
+        usb4604.i2c_bridge(),
+        Err(Error::PinUsedByI2c(ExtendedPio::Pio2))
+    ));
+    drop(pio2);
+
+    let i2c = usb4604.i2c_bridge().unwrap();
+    assert!(matches!(
+        usb4604.gpio_extended(ExtendedPio::Pio45, &acknowledged),
+        Err(Error::PinUsedByI2c(ExtendedPio::Pio45))
+    ));
+    drop(i2c);
+    assert!(
+        usb4604
+            .gpio_extended(ExtendedPio::Pio45, &acknowledged)
+            .is_ok()
+    );
+}
+
+#[test]
+fn side_effects_are_checked_before_claims() {
+    let usb4604 = Usb4604::new(SimHub::new());
+    let _i2c = usb4604.i2c_bridge().unwrap();
+    assert!(matches!(
+        usb4604.gpio_extended(ExtendedPio::Pio2, &[]),
+        Err(Error::UnacknowledgedSideEffect(SideEffect::DisablesI2c))
+    ));
+    // other pins are unaffected by the bridge
+    assert!(usb4604.gpio(Pio::Pio1).is_ok());
+}
@@ -0,0 +1,116 @@
+//! Runs the command-line tool against its simulated hub.
+#![cfg(feature = "cli")]
+
+use serde_json::{Value, json};
+use std::process::{Command, Output};
+
+fn usb4604(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_usb4604"))
+        .arg("--sim")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Run with --json, returns exit status and parsed output.
+fn usb4604_json(args: &[&str]) -> (bool, Value) {
+    let output = usb4604(&[&["--json"], args].concat());
+    let json = serde_json::from_slice(&output.stdout).unwrap();
+    (output.status.success(), json)
+}
+
+#[test]
+fn gpio_set_and_get() {
+    let (ok, pin) = usb4604_json(&["gpio", "set", "GPIO3", "high"]);
+    assert!(ok);
+    assert_eq!(
+        pin,
+        json!({ "pin": "Pio3", "number": 3, "mode": "output", "pull": "none", "output": true, "input": true })
+    );
+    // every run starts with a fresh simulated hub
+    let (ok, pin) = usb4604_json(&["gpio", "get", "3"]);
+    assert!(ok);
+    assert_eq!(pin["mode"], "input");
+}
+
+#[test]
+fn gpio_mode_keeps_pull() {
+    let (ok, pin) = usb4604_json(&["gpio", "pull", "pio8", "up"]);
+    assert!(ok);
+    assert_eq!(pin["pull"], "up");
+    assert_eq!(pin["input"], true);
+
+    let output = usb4604(&["gpio", "mode", "pio8", "output"]);
+    assert!(output.status.success());
+    let human = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(human.trim_end(), "Pio8\toutput\tnone\tlow\tlow");
+}
+
+#[test]
+fn gpio_side_effects_need_flag() {
+    let output = usb4604(&["gpio", "set", "2", "high"]);
+    assert!(!output.status.success());
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(error.contains("--allow-side-effects"), "{error}");
+
+    let (ok, pin) = usb4604_json(&["--allow-side-effects", "gpio", "set", "2", "high"]);
+    assert!(ok);
+    assert_eq!(pin["output"], true);
+}
+
+#[test]
+fn reg_write_and_read() {
+    let (ok, reg) = usb4604_json(&["reg", "write", "0x0837", "0x05"]);
+    assert!(ok);
+    assert_eq!(reg, json!({ "address": 0x0837, "value": 5 }));
+
+    let output = usb4604(&["reg", "read", "0x0833"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "0x0833: 0x00\n");
+
+    let (ok, error) = usb4604_json(&["reg", "read", "0x0830"]);
+    assert!(!ok);
+    assert!(error["error"].is_string());
+}
+
+#[test]
+fn i2c_scan_and_transfer() {
+    let (ok, scan) = usb4604_json(&["i2c", "scan"]);
+    assert!(ok);
+    assert_eq!(scan, json!({ "addresses": [0x50] }));
+
+    let output = usb4604(&["i2c", "scan", "--probe", "write"]);
+    assert!(output.status.success());
+    let grid = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        grid.lines().any(|line| line.starts_with("50: 50 --")),
+        "{grid}"
+    );
+
+    let (ok, read) = usb4604_json(&["i2c", "transfer", "0x50", "0", "--read", "2"]);
+    assert!(ok);
+    assert_eq!(read, json!({ "address": 0x50, "data": [0, 0] }));
+
+    let (ok, _) = usb4604_json(&["i2c", "write", "0x51", "0"]);
+    assert!(!ok);
+}
+
+#[test]
+fn dump_lists_every_pin() {
+    let (ok, dump) = usb4604_json(&["dump"]);
+    assert!(ok);
+    assert_eq!(dump["chip_variant"], "Usb4604");
+    assert_eq!(dump["pins"].as_array().unwrap().len(), 17);
+    assert_eq!(dump["registers"].as_array().unwrap().len(), 4 * 5);
+}
+
+#[test]
+fn invalid_arguments_are_rejected() {
+    let output = usb4604(&["gpio", "get", "GPIO4"]);
+    assert!(!output.status.success());
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        error.contains("GPIO4 is not in the register map"),
+        "{error}"
+    );
+}
@@ -0,0 +1,156 @@
+//! Transport wrapper shared by the integration tests.
+#![allow(dead_code)]
+
+use nusb::transfer::{ControlIn, ControlOut, TransferError};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Duration;
+use usb4604::Transport;
+use usb4604::sim::SimHub;
+
+pub const CMD_REG_WRITE: u8 = 0x03;
+pub const CMD_REG_READ: u8 = 0x04;
+pub const CMD_I2C_ENTER_PASSTHRU: u8 = 0x70;
+pub const CMD_I2C_WRITE: u8 = 0x71;
+pub const CMD_I2C_READ: u8 = 0x72;
+
+/// Control transfer as sent to the device.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Request {
+    In {
+        request: u8,
+        value: u16,
+        len: usize,
+    },
+    Out {
+        request: u8,
+        value: u16,
+        data: Vec<u8>,
+    },
+}
+
+impl Request {
+    pub fn reg_read(addr: u16) -> Request {
+        Request::In {
+            request: CMD_REG_READ,
+            value: addr,
+            len: 1,
+        }
+    }
+
+    pub fn reg_write(addr: u16, value: u8) -> Request {
+        Request::Out {
+            request: CMD_REG_WRITE,
+            value: addr,
+            data: vec![value],
+        }
+    }
+
+    pub fn request(&self) -> u8 {
+        match self {
+            Request::In { request, .. } | Request::Out { request, .. } => *request,
+        }
+    }
+}
+
+/// What happens to the next transfer instead of passing it to the simulator unchanged.
+#[derive(Clone, Copy)]
+pub enum Fault {
+    Pass,
+    Fail(TransferError),
+    /// Return only this many bytes of a read.
+    Truncate(usize),
+}
+
+/// Passes everything to the simulator, recording transfers and injecting faults.
+#[derive(Clone)]
+pub struct Recorder {
+    pub sim: SimHub,
+    requests: Arc<Mutex<Vec<Request>>>,
+    faults: Arc<Mutex<VecDeque<Fault>>>,
+}
+
+impl Recorder {
+    pub fn new(sim: SimHub) -> Recorder {
+        Recorder {
+            sim,
+            requests: Arc::new(Mutex::new(Vec::new())),
+            faults: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Apply faults to the next transfers, one each.
+    pub fn inject(&self, faults: &[Fault]) {
+        self.faults.lock().unwrap().extend(faults);
+    }
+
+    /// Returns and forgets the transfers recorded so far.
+    pub fn take(&self) -> Vec<Request> {
+        std::mem::take(&mut self.requests.lock().unwrap())
+    }
+
+    fn record(&self, request: Request) -> Fault {
+        self.requests.lock().unwrap().push(request);
+        self.faults
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(Fault::Pass)
+    }
+}
+
+impl Transport for Recorder {
+    fn control_in(&self, data: ControlIn, timeout: Duration) -> Result<Vec<u8>, TransferError> {
+        let request = Request::In {
+            request: data.request,
+            value: data.value,
+            len: data.length as usize,
+        };
+        match self.record(request) {
+            Fault::Pass => self.sim.control_in(data, timeout),
+            Fault::Fail(e) => Err(e),
+            Fault::Truncate(len) => {
+                let mut read = self.sim.control_in(data, timeout)?;
+                read.truncate(len);
+                Ok(read)
+            }
+        }
+    }
+
+    fn control_out(&self, data: ControlOut<'_>, timeout: Duration) -> Result<(), TransferError> {
+        let request = Request::Out {
+            request: data.request,
+            value: data.value,
+            data: data.data.to_vec(),
+        };
+        match self.record(request) {
+            Fault::Fail(e) => Err(e),
+            Fault::Pass | Fault::Truncate(_) => self.sim.control_out(data, timeout),
+        }
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a future to completion on the current thread.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(value) = future.as_mut().poll(&mut cx) {
+            return value;
+        }
+        thread::park();
+    }
+}
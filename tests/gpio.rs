@@ -0,0 +1,160 @@
+mod common;
+
+use common::{Recorder, Request, block_on};
+use embedded_hal_async::digital::Wait;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Waker};
+use std::thread;
+use std::time::Duration;
+use usb4604::sim::SimHub;
+use usb4604::usb4604_reg::{Gpio0_7Dir, Gpio0_7Input, Gpio0_7PullDown, Gpio0_7PullUp};
+use usb4604::{DropPolicy, ExtendedPio, Level, Pio, Pull, SideEffect, SmscReg, Usb4604};
+
+#[test]
+fn keep_leaves_pin_as_it_is() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    let pio0 = usb4604.output(Pio::Pio0, Some(Level::High)).unwrap();
+    assert_eq!(pio0.drop_policy(), DropPolicy::Keep);
+    drop(pio0);
+    assert_eq!(sim.output_level(0), Some(Level::High));
+}
+
+#[test]
+fn input_releases_pin_and_pulls() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    let mut pio1 = usb4604.input(Pio::Pio1, Pull::Up).unwrap();
+    pio1.set_drop_policy(DropPolicy::Input).unwrap();
+    drop(pio1);
+    assert_eq!(sim.register(Gpio0_7PullUp::ADDR), Some(0));
+
+    let mut pio0 = usb4604.output(Pio::Pio0, Some(Level::High)).unwrap();
+    pio0.set_drop_policy(DropPolicy::Input).unwrap();
+    drop(pio0);
+    assert_eq!(sim.output_level(0), None);
+}
+
+#[test]
+fn drive_makes_pin_an_output() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    let mut pio3 = usb4604.input(Pio::Pio3, Pull::Up).unwrap();
+    pio3.set_drop_policy(DropPolicy::Drive(Level::Low)).unwrap();
+    drop(pio3);
+    assert_eq!(sim.output_level(3), Some(Level::Low));
+    assert_eq!(sim.register(Gpio0_7PullUp::ADDR), Some(0));
+}
+
+#[test]
+fn restore_brings_back_input() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    drop(usb4604.input(Pio::Pio3, Pull::Down).unwrap());
+
+    let mut pio3 = usb4604.gpio(Pio::Pio3).unwrap();
+    pio3.set_drop_policy(DropPolicy::Restore).unwrap();
+    pio3.set_as_output(Some(Level::High)).unwrap();
+    assert_eq!(sim.output_level(3), Some(Level::High));
+    drop(pio3);
+    assert_eq!(sim.output_level(3), None);
+    assert_eq!(sim.register(Gpio0_7PullDown::ADDR), Some(1 << 3));
+    assert_eq!(sim.input_level(3), Some(Level::Low));
+}
+
+#[test]
+fn restore_brings_back_output() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    drop(usb4604.output(Pio::Pio8, Some(Level::High)).unwrap());
+
+    let mut pio8 = usb4604.gpio(Pio::Pio8).unwrap();
+    pio8.set_drop_policy(DropPolicy::Restore).unwrap();
+    pio8.set_as_input(Pull::Down).unwrap();
+    drop(pio8);
+    assert_eq!(sim.output_level(8), Some(Level::High));
+}
+
+#[test]
+fn opening_pin_reads_only_direction() {
+    let recorder = Recorder::new(SimHub::new());
+    let usb4604 = Usb4604::new(recorder.clone());
+    let pio0 = usb4604.gpio(Pio::Pio0).unwrap();
+    assert_eq!(recorder.take(), [Request::reg_read(Gpio0_7Dir::ADDR)]);
+    drop(pio0);
+    // nothing to write back with the default policy
+    assert_eq!(recorder.take(), []);
+}
+
+#[test]
+fn wait_for_rising_edge_sees_external_level() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    usb4604.set_poll_interval(Duration::from_millis(1));
+    sim.drive(1, Some(Level::High));
+    let mut pio1 = usb4604.input(Pio::Pio1, Pull::None).unwrap();
+    let raised = Arc::new(AtomicBool::new(false));
+    let driver = {
+        let (sim, raised) = (sim.clone(), raised.clone());
+        thread::spawn(move || {
+            // already high is not an edge
+            thread::sleep(Duration::from_millis(20));
+            sim.drive(1, Some(Level::Low));
+            thread::sleep(Duration::from_millis(20));
+            raised.store(true, Ordering::Relaxed);
+            sim.drive(1, Some(Level::High));
+        })
+    };
+    block_on(pio1.wait_for_rising_edge()).unwrap();
+    assert!(raised.load(Ordering::Relaxed));
+    driver.join().unwrap();
+}
+
+#[test]
+fn wait_for_high_returns_when_already_high() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    let mut pio1 = usb4604.input(Pio::Pio1, Pull::Up).unwrap();
+    block_on(pio1.wait_for_high()).unwrap();
+}
+
+#[test]
+fn poller_stops_when_waiter_is_dropped() {
+    let recorder = Recorder::new(SimHub::new());
+    let usb4604 = Usb4604::new(recorder.clone());
+    usb4604.set_poll_interval(Duration::from_millis(1));
+    let mut pio1 = usb4604.input(Pio::Pio1, Pull::Down).unwrap();
+    {
+        let mut wait = pin!(pio1.wait_for_high());
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(wait.as_mut().poll(&mut cx).is_pending());
+        thread::sleep(Duration::from_millis(20));
+    }
+    let requests = recorder.take();
+    assert!(requests.contains(&Request::reg_read(Gpio0_7Input::ADDR)));
+    // give the poller time to notice, then it must be quiet
+    thread::sleep(Duration::from_millis(20));
+    recorder.take();
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(recorder.take(), []);
+
+    // a new waiter starts a new poller
+    recorder.sim.drive(1, Some(Level::High));
+    block_on(pio1.wait_for_high()).unwrap();
+}
+
+#[test]
+fn pio_is_none_for_extended_pins() {
+    let usb4604 = Usb4604::new(SimHub::new());
+    let pio0 = usb4604.gpio(Pio::Pio0).unwrap();
+    assert_eq!(pio0.pio(), Some(Pio::Pio0));
+    assert_eq!(pio0.extended_pio(), ExtendedPio::Pio0);
+    let pio5 = usb4604
+        .gpio_extended(ExtendedPio::Pio5, &[SideEffect::Unverified])
+        .unwrap();
+    assert_eq!(pio5.pio(), None);
+    assert_eq!(pio5.extended_pio(), ExtendedPio::Pio5);
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{Recorder, Request};
+use usb4604::sim::SimHub;
+use usb4604::usb4604_reg::*;
+use usb4604::{Level, Pio, Pull, SmscReg, Usb4604};
+
+fn setup() -> (Recorder, Usb4604) {
+    let recorder = Recorder::new(SimHub::new());
+    let usb4604 = Usb4604::new(recorder.clone());
+    (recorder, usb4604)
+}
+
+#[test]
+fn output_is_one_read_modify_write_per_register() {
+    let (recorder, usb4604) = setup();
+    // bank of GPIO0-7 first, its pins are not adjacent in the group
+    let mut group = usb4604
+        .pin_group(&[Pio::Pio0, Pio::Pio8, Pio::Pio1])
+        .unwrap();
+    recorder.take();
+    group.set_as_output(0b111).unwrap();
+    assert_eq!(
+        recorder.take(),
+        [
+            // levels first, so that pins start driving the right one
+            Request::reg_read(Gpio0_7Output::ADDR),
+            Request::reg_write(Gpio0_7Output::ADDR, 0b11),
+            Request::reg_read(Gpio8_10Output::ADDR),
+            Request::reg_write(Gpio8_10Output::ADDR, 0b1),
+            Request::reg_read(Gpio0_7Dir::ADDR),
+            Request::reg_write(Gpio0_7Dir::ADDR, 0b11),
+            Request::reg_read(Gpio8_10Dir::ADDR),
+            Request::reg_write(Gpio8_10Dir::ADDR, 0b1),
+            // pulls are already off, nothing to write
+            Request::reg_read(Gpio0_7PullUp::ADDR),
+            Request::reg_read(Gpio0_7PullDown::ADDR),
+            Request::reg_read(Gpio8_10PullUp::ADDR),
+            Request::reg_read(Gpio8_10PullDown::ADDR),
+        ]
+    );
+    let sim = &recorder.sim;
+    assert_eq!(sim.output_level(0), Some(Level::High));
+    assert_eq!(sim.output_level(1), Some(Level::High));
+    assert_eq!(sim.output_level(8), Some(Level::High));
+
+    group.write(0b010).unwrap();
+    assert_eq!(
+        recorder.take(),
+        [
+            Request::reg_read(Gpio0_7Output::ADDR),
+            Request::reg_write(Gpio0_7Output::ADDR, 0),
+            Request::reg_read(Gpio8_10Output::ADDR),
+        ]
+    );
+}
+
+#[test]
+fn read_maps_pins_to_bits_in_group_order() {
+    let (recorder, usb4604) = setup();
+    let mut group = usb4604
+        .pin_group(&[Pio::Pio0, Pio::Pio8, Pio::Pio1])
+        .unwrap();
+    group.set_as_input(Pull::None).unwrap();
+    let sim = &recorder.sim;
+    sim.drive(0, Some(Level::Low));
+    sim.drive(8, Some(Level::High));
+    sim.drive(1, Some(Level::High));
+    recorder.take();
+    assert_eq!(group.read().unwrap(), 0b110);
+    // one read per input register
+    assert_eq!(
+        recorder.take(),
+        [
+            Request::reg_read(Gpio0_7Input::ADDR),
+            Request::reg_read(Gpio8_10Input::ADDR),
+        ]
+    );
+}
@@ -0,0 +1,336 @@
+mod common;
+
+use common::{CMD_I2C_READ, CMD_I2C_WRITE, Fault, Recorder, Request};
+use embedded_hal::i2c::NoAcknowledgeSource;
+use nusb::transfer::TransferError;
+use std::time::Duration;
+use usb4604::sim::{I2cTarget, MemoryTarget, SimHub};
+use usb4604::{Error, I2c, I2cBridge, I2cError, Operation, Pio, ScanOptions, Usb4604};
+
+/// I2C transfer as seen by the bridge.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Transfer {
+    is_read: bool,
+    len: usize,
+    start: bool,
+    stop: bool,
+    nack: bool,
+}
+
+/// I2C transfers recorded since the last call, register access is left out.
+fn transfers(recorder: &Recorder) -> Vec<Transfer> {
+    recorder
+        .take()
+        .into_iter()
+        .filter_map(|request| {
+            let (value, len) = match request {
+                Request::In {
+                    request: CMD_I2C_READ,
+                    value,
+                    len,
+                } => (value, len),
+                Request::Out {
+                    request: CMD_I2C_WRITE,
+                    value,
+                    data,
+                } => (value, data.len()),
+                _ => return None,
+            };
+            Some(Transfer {
+                is_read: value & 1 != 0,
+                len,
+                start: value & (1 << 9) != 0,
+                stop: value & (1 << 8) != 0,
+                nack: value & (1 << 10) != 0,
+            })
+        })
+        .collect()
+}
+
+fn write(len: usize, start: bool, stop: bool) -> Transfer {
+    Transfer {
+        is_read: false,
+        len,
+        start,
+        stop,
+        nack: false,
+    }
+}
+
+fn read(len: usize, start: bool, stop: bool) -> Transfer {
+    Transfer {
+        is_read: true,
+        len,
+        start,
+        stop,
+        nack: stop,
+    }
+}
+
+fn setup() -> (Recorder, MemoryTarget, Usb4604) {
+    let sim = SimHub::new();
+    let eeprom = MemoryTarget::new(256);
+    sim.add_i2c_target(0x50, eeprom.clone());
+    let recorder = Recorder::new(sim);
+    let usb4604 = Usb4604::new(recorder.clone());
+    (recorder, eeprom, usb4604)
+}
+
+#[test]
+fn long_write_is_split_into_one_transaction() {
+    let (recorder, eeprom, usb4604) = setup();
+    let mut i2c = usb4604.i2c_bridge().unwrap();
+    i2c.set_max_chunk_size(4);
+    let data: Vec<u8> = (0..10).collect();
+    i2c.write(0x50, &data).unwrap();
+    assert_eq!(
+        transfers(&recorder),
+        [
+            write(4, true, false),
+            write(4, false, false),
+            write(2, false, true)
+        ]
+    );
+    assert_eq!(&eeprom.memory()[..9], &data[1..]);
+}
+
+#[test]
+fn long_read_nacks_only_last_chunk() {
+    let (recorder, eeprom, usb4604) = setup();
+    eeprom.load(0x20, &[1, 2, 3, 4, 5, 6]);
+    let mut i2c = usb4604.i2c_bridge().unwrap();
+    i2c.set_max_chunk_size(4);
+    let mut buf = [0u8; 6];
+    i2c.write_read(0x50, &[0x20], &mut buf).unwrap();
+    assert_eq!(buf, [1, 2, 3, 4, 5, 6]);
+    assert_eq!(
+        transfers(&recorder),
+        [
+            write(1, true, false),
+            read(4, true, false),
+            read(2, false, true)
+        ]
+    );
+}
+
+#[test]
+fn zero_length_write_addresses_target() {
+    let (recorder, _, usb4604) = setup();
+    let mut i2c = usb4604.i2c_bridge().unwrap();
+    i2c.transaction(0x50, &mut [Operation::Write(&[])]).unwrap();
+    assert_eq!(transfers(&recorder), [write(0, true, true)]);
+}
+
+#[test]
+fn chunk_size_is_clamped() {
+    let (_, _, usb4604) = setup();
+    let mut i2c = usb4604.i2c_bridge().unwrap();
+    assert_eq!(i2c.max_chunk_size(), 512);
+    i2c.set_max_chunk_size(0);
+    assert_eq!(i2c.max_chunk_size(), 1);
+    i2c.set_max_chunk_size(100_000);
+    assert_eq!(i2c.max_chunk_size(), 65535);
+}
+
+/// Accepts at most limit bytes per write, NACKs the rest.
+struct LimitedTarget {
+    limit: usize,
+    received: usize,
+}
+
+impl I2cTarget for LimitedTarget {
+    fn start(&mut self, _is_read: bool) -> bool {
+        self.received = 0;
+        true
+    }
+
+    fn write(&mut self, data: &[u8]) -> bool {
+        self.received += data.len();
+        self.received <= self.limit
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> bool {
+        buf.fill(0);
+        true
+    }
+}
+
+#[test]
+fn short_read_reports_received_bytes() {
+    let (recorder, eeprom, usb4604) = setup();
+    eeprom.load(0, &[1, 2, 3, 4, 5, 6]);
+    let mut i2c = usb4604.i2c_bridge().unwrap();
+    i2c.set_max_chunk_size(4);
+    recorder.inject(&[Fault::Pass, Fault::Pass, Fault::Truncate(1)]);
+    let mut buf = [0u8; 6];
+    assert_eq!(
+        i2c.write_read(0x50, &[0], &mut buf),
+        Err(I2cError::ShortRead {
+            requested: 6,
+            received: 5
+        })
+    );
+    assert_eq!(buf, [1, 2, 3, 4, 5, 0]);
+}
+
+#[test]
+fn nack_source_follows_failed_transfer() {
+    let (_, _, usb4604) = setup();
+    let mut i2c = usb4604.i2c_bridge().unwrap();
+    let address = Err(I2cError::Nack(NoAcknowledgeSource::Address));
+    assert_eq!(i2c.read(0x51, &mut [0]), address);
+    assert_eq!(i2c.write(0x51, &[]), address);
+    // address and data go out in the same transfer
+    assert_eq!(
+        i2c.write(0x51, &[0]),
+        Err(I2cError::Nack(NoAcknowledgeSource::Unknown))
+    );
+}
+
+#[test]
+fn nack_of_write_continuation_is_data() {
+    let sim = SimHub::new();
+    sim.add_i2c_target(
+        0x52,
+        LimitedTarget {
+            limit: 2,
+            received: 0,
+        },
+    );
+    let usb4604 = Usb4604::new(sim);
+    let mut i2c = usb4604.i2c_bridge().unwrap();
+    i2c.set_max_chunk_size(2);
+    assert_eq!(
+        i2c.write(0x52, &[1, 2, 3, 4]),
+        Err(I2cError::Nack(NoAcknowledgeSource::Data))
+    );
+}
+
+#[test]
+fn cancelled_transfer_is_timeout() {
+    let (recorder, _, usb4604) = setup();
+    let mut i2c = usb4604.i2c_bridge().unwrap();
+    recorder.inject(&[Fault::Fail(TransferError::Cancelled)]);
+    assert_eq!(i2c.write(0x50, &[0]), Err(I2cError::Timeout));
+    recorder.inject(&[Fault::Fail(TransferError::Fault)]);
+    assert_eq!(
+        i2c.write(0x50, &[0]),
+        Err(I2cError::Other(TransferError::Fault))
+    );
+}
+
+fn retrying_bridge(usb4604: &Usb4604, retry_on_nack: bool) -> I2cBridge {
+    usb4604
+        .i2c_bridge_builder()
+        .retries(2)
+        .retry_on_nack(retry_on_nack)
+        .backoff(Duration::from_millis(1))
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn nack_is_retried_only_if_enabled() {
+    let (recorder, _, usb4604) = setup();
+    let mut i2c = retrying_bridge(&usb4604, true);
+    recorder.inject(&[Fault::Fail(TransferError::Stall)]);
+    i2c.write(0x50, &[0]).unwrap();
+    assert_eq!(transfers(&recorder), [write(1, true, true); 2]);
+    drop(i2c);
+
+    let mut i2c = retrying_bridge(&usb4604, false);
+    recorder.inject(&[Fault::Fail(TransferError::Stall)]);
+    assert_eq!(
+        i2c.write(0x50, &[0]),
+        Err(I2cError::Nack(NoAcknowledgeSource::Unknown))
+    );
+    assert_eq!(transfers(&recorder), [write(1, true, true)]);
+}
+
+#[test]
+fn failure_after_first_transfer_is_not_retried() {
+    let (recorder, _, usb4604) = setup();
+    let mut i2c = retrying_bridge(&usb4604, true);
+    recorder.inject(&[Fault::Pass, Fault::Fail(TransferError::Fault)]);
+    assert_eq!(
+        i2c.write_read(0x50, &[0], &mut [0; 2]),
+        Err(I2cError::Other(TransferError::Fault))
+    );
+    assert_eq!(
+        transfers(&recorder),
+        [write(1, true, false), read(2, true, true)]
+    );
+}
+
+#[test]
+fn timeout_is_retried_only_without_data_written() {
+    let (recorder, _, usb4604) = setup();
+    let mut i2c = retrying_bridge(&usb4604, false);
+    recorder.inject(&[Fault::Fail(TransferError::Cancelled)]);
+    assert_eq!(i2c.write(0x50, &[0]), Err(I2cError::Timeout));
+    assert_eq!(transfers(&recorder), [write(1, true, true)]);
+
+    recorder.inject(&[Fault::Fail(TransferError::Cancelled)]);
+    i2c.read(0x50, &mut [0]).unwrap();
+    assert_eq!(transfers(&recorder), [read(1, true, true); 2]);
+}
+
+#[test]
+fn register_access_is_retried() {
+    let sim = SimHub::new();
+    let recorder = Recorder::new(sim);
+    let usb4604 = Usb4604::builder()
+        .retries(1)
+        .backoff(Duration::from_millis(1))
+        .build(recorder.clone());
+    recorder.inject(&[Fault::Fail(TransferError::Stall)]);
+    assert!(usb4604.gpio(Pio::Pio0).is_ok());
+
+    recorder.inject(&[Fault::Fail(TransferError::Stall); 2]);
+    assert!(matches!(
+        usb4604.gpio(Pio::Pio1),
+        Err(Error::TransferError(TransferError::Stall))
+    ));
+    // the device is gone, retrying would not help
+    recorder.inject(&[Fault::Fail(TransferError::Disconnected)]);
+    assert!(matches!(
+        usb4604.gpio(Pio::Pio3),
+        Err(Error::TransferError(TransferError::Disconnected))
+    ));
+}
+
+#[test]
+fn scan_finds_targets() {
+    let (recorder, _, usb4604) = setup();
+    recorder.sim.add_i2c_target(0x20, MemoryTarget::new(16));
+    let mut i2c = usb4604.i2c_bridge().unwrap();
+    recorder.take();
+    let result = i2c
+        .scan_with(&ScanOptions::new().range(0x20..=0x50))
+        .unwrap();
+    assert_eq!(result.addresses(), [0x20, 0x50]);
+    assert!(result.is_probed(0x20) && !result.is_probed(0x51));
+    let probes = transfers(&recorder);
+    // auto picks a zero-length write, except for EEPROM addresses
+    assert_eq!(probes[0], write(0, true, true));
+    assert_eq!(probes.last(), Some(&read(1, true, true)));
+
+    let result = i2c.scan().unwrap();
+    assert_eq!(result.addresses(), [0x20, 0x50]);
+    let grid = result.to_string();
+    let grid: Vec<_> = grid.lines().map(str::trim_end).collect();
+    assert_eq!(
+        grid,
+        [
+            "     0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f",
+            "00:                         -- -- -- -- -- -- -- --",
+            "10: -- -- -- -- -- -- -- -- -- -- -- -- -- -- -- --",
+            "20: 20 -- -- -- -- -- -- -- -- -- -- -- -- -- -- --",
+            "30: -- -- -- -- -- -- -- -- -- -- -- -- -- -- -- --",
+            "40: -- -- -- -- -- -- -- -- -- -- -- -- -- -- -- --",
+            "50: 50 -- -- -- -- -- -- -- -- -- -- -- -- -- -- --",
+            "60: -- -- -- -- -- -- -- -- -- -- -- -- -- -- -- --",
+            "70: -- -- -- -- -- -- -- --",
+        ]
+    );
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use common::{CMD_REG_WRITE, Fault, Recorder, Request};
+use nusb::transfer::{ControlIn, ControlOut, TransferError};
+use std::time::Duration;
+use strum::IntoEnumIterator;
+use usb4604::sim::SimHub;
+use usb4604::usb4604_reg::{Gpio0_7Dir, Gpio0_7Output};
+use usb4604::{
+    ChipVariant, Error, ExtendedPio, Level, PinCapabilities, Pio, Pull, SmscReg, Transport, Usb4604,
+};
+
+/// Ignores writes of some bits of one register, like a pin that is not controllable from USB.
+struct StuckBits {
+    sim: SimHub,
+    addr: u16,
+    mask: u8,
+}
+
+impl Transport for StuckBits {
+    fn control_in(&self, data: ControlIn, timeout: Duration) -> Result<Vec<u8>, TransferError> {
+        self.sim.control_in(data, timeout)
+    }
+
+    fn control_out(&self, data: ControlOut<'_>, timeout: Duration) -> Result<(), TransferError> {
+        if data.request == CMD_REG_WRITE && data.value == self.addr {
+            let old = self.sim.register(self.addr).unwrap();
+            let value = (data.data[0] & !self.mask) | (old & self.mask);
+            return self.sim.control_out(
+                ControlOut {
+                    data: &[value],
+                    ..data
+                },
+                timeout,
+            );
+        }
+        self.sim.control_out(data, timeout)
+    }
+}
+
+/// Values of every GPIO register of the chip.
+fn registers(sim: &SimHub) -> Vec<Option<u8>> {
+    ChipVariant::Usb4604
+        .banks()
+        .flat_map(|bank| {
+            [
+                bank.dir,
+                bank.output,
+                bank.input,
+                bank.pull_up,
+                bank.pull_down,
+            ]
+        })
+        .map(|addr| sim.register(addr))
+        .collect()
+}
+
+#[test]
+fn probe_reports_writable_bits_and_restores_registers() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(StuckBits {
+        sim: sim.clone(),
+        addr: Gpio0_7Dir::ADDR,
+        mask: 1 << 3,
+    });
+    let _pio0 = usb4604.output(Pio::Pio0, Some(Level::High)).unwrap();
+    let _pio1 = usb4604.input(Pio::Pio1, Pull::Up).unwrap();
+    let before = registers(&sim);
+
+    let report = usb4604.probe_pins(&[]).unwrap();
+    assert_eq!(registers(&sim), before);
+    // only verified pins have no side effects
+    let pios: Vec<_> = report.iter().map(|pin| pin.pio).collect();
+    let verified: Vec<ExtendedPio> = Pio::iter().map(Into::into).collect();
+    assert_eq!(pios, verified);
+    for pin in &report {
+        if pin.pio == Pio::Pio3 {
+            assert_eq!(
+                *pin,
+                PinCapabilities {
+                    pio: ExtendedPio::Pio3,
+                    direction: false,
+                    output: true,
+                    pull_up: true,
+                    pull_down: true,
+                    input_follows_output: false,
+                }
+            );
+        } else {
+            assert!(pin.is_working() && pin.input_follows_output, "{pin:?}");
+        }
+    }
+}
+
+#[test]
+fn probe_restores_register_after_failed_write() {
+    let recorder = Recorder::new(SimHub::new());
+    let usb4604 = Usb4604::new(recorder.clone());
+    // read, set and read back direction of PIO0, then fail to clear it
+    recorder.inject(&[
+        Fault::Pass,
+        Fault::Pass,
+        Fault::Pass,
+        Fault::Fail(TransferError::Stall),
+    ]);
+    assert!(matches!(
+        usb4604.probe_pins(&[]),
+        Err(Error::TransferError(TransferError::Stall))
+    ));
+    assert_eq!(
+        recorder.take().last(),
+        Some(&Request::reg_write(Gpio0_7Dir::ADDR, 0))
+    );
+    assert_eq!(recorder.sim.register(Gpio0_7Dir::ADDR), Some(0));
+}
+
+#[test]
+fn probe_releases_pin_after_failed_input_check() {
+    let recorder = Recorder::new(SimHub::new());
+    let usb4604 = Usb4604::new(recorder.clone());
+    // 4 bits of PIO0 probed with 6 transfers each, then dir and output are read, the pin is made an output
+    // driving low, and reading input fails
+    let mut faults = vec![Fault::Pass; 4 * 6 + 4];
+    faults.push(Fault::Fail(TransferError::Stall));
+    recorder.inject(&faults);
+    assert!(usb4604.probe_pins(&[]).is_err());
+    assert_eq!(
+        recorder.take()[4 * 6 + 5..],
+        [
+            Request::reg_write(Gpio0_7Output::ADDR, 0),
+            Request::reg_write(Gpio0_7Dir::ADDR, 0),
+        ]
+    );
+    assert_eq!(recorder.sim.output_level(0), None);
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::{CMD_I2C_ENTER_PASSTHRU, Fault, Recorder, Request};
+use nusb::transfer::TransferError;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use usb4604::sim::SimHub;
+use usb4604::usb4604_reg::{Gpio0_7Output, Gpio0_7PullUp};
+use usb4604::{Level, Pio, Pull, SmscReg, Usb4604};
+
+#[test]
+fn reconnect_replays_pins_and_passthru() {
+    let recorder = Recorder::new(SimHub::new());
+    let usb4604 = Usb4604::builder()
+        .retries(1)
+        .backoff(Duration::from_millis(1))
+        .build(recorder.clone());
+    let fresh = Recorder::new(SimHub::new());
+    let reopened = Arc::new(AtomicUsize::new(0));
+    {
+        let fresh = fresh.clone();
+        let reopened = reopened.clone();
+        usb4604.set_reconnect_with(Duration::from_secs(1), move |_| {
+            reopened.fetch_add(1, Ordering::Relaxed);
+            Ok(Arc::new(fresh.clone()))
+        });
+    }
+    let mut pio0 = usb4604.output(Pio::Pio0, Some(Level::High)).unwrap();
+    let _pio1 = usb4604.input(Pio::Pio1, Pull::Up).unwrap();
+    let _i2c = usb4604.i2c_bridge().unwrap();
+    recorder.take();
+
+    recorder.inject(&[Fault::Fail(TransferError::Disconnected)]);
+    // replay goes through the same retry policy
+    fresh.inject(&[Fault::Fail(TransferError::Stall)]);
+    pio0.toggle().unwrap();
+    assert_eq!(reopened.load(Ordering::Relaxed), 1);
+    assert_eq!(recorder.take(), [Request::reg_read(Gpio0_7Output::ADDR)]);
+
+    let sim = &fresh.sim;
+    assert_eq!(sim.output_level(0), Some(Level::Low));
+    assert_eq!(sim.output_level(1), None);
+    assert_eq!(sim.register(Gpio0_7PullUp::ADDR), Some(0b10));
+    assert_eq!(sim.input_level(1), Some(Level::High));
+    assert!(sim.is_i2c_passthru());
+
+    // failed read is sent once more after the replay, then toggle carries on
+    let requests = fresh.take();
+    let passthru = requests
+        .iter()
+        .position(|r| r.request() == CMD_I2C_ENTER_PASSTHRU)
+        .unwrap();
+    assert_eq!(
+        requests[passthru + 1..],
+        [
+            Request::reg_read(Gpio0_7Output::ADDR),
+            Request::reg_read(Gpio0_7Output::ADDR),
+            Request::reg_write(Gpio0_7Output::ADDR, 0),
+        ]
+    );
+}
+
+#[test]
+fn disconnect_without_reconnect_fails() {
+    let recorder = Recorder::new(SimHub::new());
+    let usb4604 = Usb4604::new(recorder.clone());
+    let mut pio0 = usb4604.output(Pio::Pio0, Some(Level::High)).unwrap();
+    recorder.inject(&[Fault::Fail(TransferError::Disconnected)]);
+    assert!(pio0.toggle().is_err());
+    assert_eq!(recorder.sim.output_level(0), Some(Level::High));
+}
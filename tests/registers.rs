@@ -0,0 +1,132 @@
+mod common;
+
+use common::{Fault, Recorder, Request};
+use nusb::transfer::{ControlIn, ControlOut, TransferError};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+use usb4604::sim::SimHub;
+use usb4604::usb4604_reg::{Gpio0_7Dir, Gpio0_7Input, Gpio0_7Output};
+use usb4604::{ChipVariant, Level, Pio, SmscReg, Transport, Usb4604};
+
+fn cached() -> (Recorder, Usb4604) {
+    let recorder = Recorder::new(SimHub::new());
+    let usb4604 = Usb4604::new(recorder.clone());
+    usb4604.set_shadow_cache(true);
+    (recorder, usb4604)
+}
+
+#[test]
+fn empty_register_read_is_an_error() {
+    let recorder = Recorder::new(SimHub::new());
+    let usb4604 = Usb4604::new(recorder.clone());
+    recorder.inject(&[Fault::Truncate(0)]);
+    assert_eq!(
+        usb4604.read_addr(Gpio0_7Dir::ADDR),
+        Err(TransferError::Fault)
+    );
+    assert_eq!(usb4604.read_addr(Gpio0_7Dir::ADDR), Ok(0));
+}
+
+#[test]
+fn cached_toggle_is_one_write() {
+    let (recorder, usb4604) = cached();
+    let mut pio0 = usb4604.output(Pio::Pio0, Some(Level::Low)).unwrap();
+    pio0.toggle().unwrap();
+    recorder.take();
+    pio0.toggle().unwrap();
+    assert_eq!(
+        recorder.take(),
+        [Request::reg_write(Gpio0_7Output::ADDR, 0)]
+    );
+    assert_eq!(recorder.sim.output_level(0), Some(Level::Low));
+}
+
+#[test]
+fn input_is_never_cached() {
+    let (recorder, usb4604) = cached();
+    usb4604.read_addr(Gpio0_7Input::ADDR).unwrap();
+    usb4604.read_addr(Gpio0_7Input::ADDR).unwrap();
+    assert_eq!(
+        recorder.take(),
+        [
+            Request::reg_read(Gpio0_7Input::ADDR),
+            Request::reg_read(Gpio0_7Input::ADDR)
+        ]
+    );
+}
+
+#[test]
+fn invalidate_forces_read() {
+    let (recorder, usb4604) = cached();
+    usb4604.read_addr(Gpio0_7Dir::ADDR).unwrap();
+    usb4604.read_addr(Gpio0_7Dir::ADDR).unwrap();
+    assert_eq!(recorder.take(), [Request::reg_read(Gpio0_7Dir::ADDR)]);
+
+    usb4604.invalidate();
+    usb4604.read_addr(Gpio0_7Dir::ADDR).unwrap();
+    assert_eq!(recorder.take(), [Request::reg_read(Gpio0_7Dir::ADDR)]);
+}
+
+#[test]
+fn sync_reads_every_cached_register() {
+    let (recorder, usb4604) = cached();
+    usb4604.read_addr(Gpio0_7Dir::ADDR).unwrap();
+    // changed behind the cache's back, e.g. by another process
+    let other = Usb4604::new(recorder.sim.clone());
+    other.write_addr(Gpio0_7Dir::ADDR, 1).unwrap();
+    recorder.take();
+
+    usb4604.sync().unwrap();
+    let configuration: Vec<_> = ChipVariant::Usb4604
+        .banks()
+        .flat_map(|bank| [bank.dir, bank.output, bank.pull_up, bank.pull_down])
+        .map(Request::reg_read)
+        .collect();
+    assert_eq!(recorder.take(), configuration);
+    assert_eq!(usb4604.read_addr(Gpio0_7Dir::ADDR), Ok(1));
+    assert_eq!(recorder.take(), []);
+}
+
+/// Lets other threads run after every transfer, so that unsynchronized read-modify-writes interleave.
+struct Yielding(SimHub);
+
+impl Transport for Yielding {
+    fn control_in(&self, data: ControlIn, timeout: Duration) -> Result<Vec<u8>, TransferError> {
+        let result = self.0.control_in(data, timeout);
+        thread::yield_now();
+        result
+    }
+
+    fn control_out(&self, data: ControlOut<'_>, timeout: Duration) -> Result<(), TransferError> {
+        let result = self.0.control_out(data, timeout);
+        thread::yield_now();
+        result
+    }
+}
+
+#[test]
+fn concurrent_toggles_of_one_bank_are_not_lost() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(Yielding(sim.clone()));
+    let start = Arc::new(Barrier::new(2));
+    let threads: Vec<_> = [Pio::Pio0, Pio::Pio1]
+        .into_iter()
+        .map(|pio| {
+            let mut pin = usb4604.output(pio, Some(Level::Low)).unwrap();
+            let start = start.clone();
+            thread::spawn(move || {
+                start.wait();
+                // odd, so that every pin ends up high
+                for _ in 0..1001 {
+                    pin.toggle().unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(sim.output_level(0), Some(Level::High));
+    assert_eq!(sim.output_level(1), Some(Level::High));
+}
@@ -0,0 +1,76 @@
+use std::panic::{self, AssertUnwindSafe};
+use usb4604::sim::SimHub;
+use usb4604::{Error, ExtendedPio, Level, Pio, Pull, SafeState, SideEffect, Usb4604};
+
+fn setup() -> (SimHub, Usb4604) {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    let state = SafeState::new()
+        .output(Pio::Pio0, Level::Low)
+        .input(Pio::Pio1, Pull::Down);
+    usb4604.set_safe_state(Some(state), &[]).unwrap();
+    (sim, usb4604)
+}
+
+#[test]
+fn apply_bypasses_pin_handles() {
+    let (sim, usb4604) = setup();
+    let _pio0 = usb4604.output(Pio::Pio0, Some(Level::High)).unwrap();
+    let _pio1 = usb4604.output(Pio::Pio1, Some(Level::High)).unwrap();
+    usb4604.apply_safe_state().unwrap();
+    assert_eq!(sim.output_level(0), Some(Level::Low));
+    assert_eq!(sim.output_level(1), None);
+    assert_eq!(sim.input_level(1), Some(Level::Low));
+    // pins stay claimed
+    assert!(matches!(usb4604.gpio(Pio::Pio0), Err(Error::PinInUse(_))));
+}
+
+#[test]
+fn nothing_happens_without_profile() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    let _pio0 = usb4604.output(Pio::Pio0, Some(Level::High)).unwrap();
+    usb4604.apply_safe_state().unwrap();
+    assert_eq!(sim.output_level(0), Some(Level::High));
+}
+
+#[test]
+fn guard_applies_on_drop() {
+    let (sim, usb4604) = setup();
+    let pio0 = usb4604.output(Pio::Pio0, Some(Level::High)).unwrap();
+    let guard = usb4604.safe_state_guard();
+    assert_eq!(sim.output_level(0), Some(Level::High));
+    drop(guard);
+    assert_eq!(sim.output_level(0), Some(Level::Low));
+    drop(pio0);
+}
+
+#[test]
+fn guard_applies_during_unwind() {
+    let (sim, usb4604) = setup();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let _guard = usb4604.safe_state_guard();
+        let _pio0 = usb4604.output(Pio::Pio0, Some(Level::High)).unwrap();
+        panic!("test failed while the pin was high");
+    }));
+    assert!(result.is_err());
+    assert_eq!(sim.output_level(0), Some(Level::Low));
+}
+
+#[test]
+fn side_effects_must_be_acknowledged() {
+    let (sim, usb4604) = setup();
+    let state = SafeState::new().output(ExtendedPio::Pio2, Level::High);
+    assert!(matches!(
+        usb4604.set_safe_state(Some(state.clone()), &[]),
+        Err(Error::UnacknowledgedSideEffect(SideEffect::DisablesI2c))
+    ));
+    // previous profile is kept
+    usb4604.apply_safe_state().unwrap();
+    assert_eq!(sim.output_level(0), Some(Level::Low));
+
+    let acknowledged = [SideEffect::DisablesI2c, SideEffect::Unverified];
+    usb4604.set_safe_state(Some(state), &acknowledged).unwrap();
+    usb4604.apply_safe_state().unwrap();
+    assert_eq!(sim.output_level(2), Some(Level::High));
+}
@@ -0,0 +1,76 @@
+use usb4604::sim::{MemoryTarget, SimHub};
+use usb4604::{I2c, I2cError, Level, Mode, Pio, Pull, Usb4604};
+
+#[test]
+fn push_pull_output_drives_pin() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    let mut pio0 = usb4604.output(Pio::Pio0, Some(Level::Low)).unwrap();
+    assert_eq!(sim.output_level(0), Some(Level::Low));
+    pio0.toggle().unwrap();
+    assert_eq!(sim.output_level(0), Some(Level::High));
+    pio0.set_low().unwrap();
+    assert_eq!(sim.output_level(0), Some(Level::Low));
+}
+
+#[test]
+fn input_follows_pull_and_external_level() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    let input = usb4604.input(Pio::Pio1, Pull::Up).unwrap();
+    assert!(input.is_high().unwrap());
+    sim.drive(1, Some(Level::Low));
+    assert!(input.is_low().unwrap());
+    sim.drive(1, None);
+    assert!(input.is_high().unwrap());
+}
+
+#[test]
+fn flex_reads_mode_from_ic() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    let pio8 = usb4604.output(Pio::Pio8, Some(Level::High)).unwrap();
+    drop(pio8);
+    let mut pio8 = usb4604.gpio(Pio::Pio8).unwrap();
+    assert_eq!(pio8.mode(), Mode::OutputPushPull);
+    pio8.set_as_input(Pull::Down).unwrap();
+    assert_eq!(sim.output_level(8), None);
+    assert_eq!(sim.input_level(8), Some(Level::Low));
+}
+
+#[test]
+fn open_drain_releases_line_when_high() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim.clone());
+    let mut pio3 = usb4604.open_drain(Pio::Pio3, Pull::Up).unwrap();
+    pio3.set_low().unwrap();
+    assert_eq!(sim.output_level(3), Some(Level::Low));
+    pio3.set_level(Level::High).unwrap();
+    assert_eq!(sim.output_level(3), None);
+    assert_eq!(sim.input_level(3), Some(Level::High));
+}
+
+#[test]
+fn i2c_bridge_writes_and_reads_memory() {
+    let sim = SimHub::new();
+    let eeprom = MemoryTarget::new(256);
+    sim.add_i2c_target(0x50, eeprom.clone());
+    let usb4604 = Usb4604::new(sim.clone());
+    let mut i2c = usb4604.i2c_bridge().unwrap();
+    assert!(sim.is_i2c_passthru());
+
+    i2c.write(0x50, &[0x10, 1, 2, 3]).unwrap();
+    assert_eq!(&eeprom.memory()[0x10..0x13], &[1, 2, 3]);
+    let mut data = [0u8; 3];
+    i2c.write_read(0x50, &[0x10], &mut data).unwrap();
+    assert_eq!(data, [1, 2, 3]);
+}
+
+#[test]
+fn i2c_missing_target_nacks() {
+    let sim = SimHub::new();
+    let usb4604 = Usb4604::new(sim);
+    let mut i2c = usb4604.i2c_bridge().unwrap();
+    assert!(matches!(i2c.write(0x51, &[0]), Err(I2cError::Nack(_))));
+    assert_eq!(i2c.write(0x80, &[0]), Err(I2cError::WrongAddress));
+}